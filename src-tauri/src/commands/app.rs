@@ -0,0 +1,162 @@
+use std::{ops::Deref, path::PathBuf, sync::Arc, time::Duration};
+
+use eyre::Context;
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    commands::{CmdError, CmdResult},
+    database::{
+        backup::{
+            backup_exists, backups_dir, create_backup, list_backups, prune_backups, restore_backup,
+            Backup,
+        },
+        entity::{server::ServerId, setting::Setting},
+        migrations::{rollback, MigrationStatus},
+        DbPool, DATABASE_FILE_NAME,
+    },
+    server::{
+        lock::{lock_servers, IdleLock, IDLE_TIMEOUT_SETTING},
+        ServerStore,
+    },
+};
+
+/// Lock the app, unloading all password protected servers
+#[tauri::command]
+pub async fn app_lock(
+    app: AppHandle,
+    server_store: State<'_, Arc<ServerStore>>,
+) -> CmdResult<Vec<ServerId>> {
+    let unloaded = lock_servers(&app, &server_store).await;
+    Ok(unloaded)
+}
+
+/// Report user activity from the webview, resetting the idle lock
+#[tauri::command]
+pub fn app_report_activity(idle_lock: State<'_, Arc<IdleLock>>) {
+    idle_lock.touch();
+}
+
+/// Get the idle lock timeout in seconds, [None] when the lock is disabled
+#[tauri::command]
+pub fn app_get_idle_timeout(idle_lock: State<'_, Arc<IdleLock>>) -> Option<u64> {
+    idle_lock.timeout().map(|timeout| timeout.as_secs())
+}
+
+/// Set the idle lock timeout in seconds, [None] disables the lock
+#[tauri::command]
+pub async fn app_set_idle_timeout(
+    db: State<'_, DbPool>,
+    idle_lock: State<'_, Arc<IdleLock>>,
+    timeout: Option<u64>,
+) -> CmdResult<()> {
+    Setting::set(db.deref(), IDLE_TIMEOUT_SETTING, &timeout).await?;
+    idle_lock.set_timeout(timeout.map(Duration::from_secs));
+    Ok(())
+}
+
+/// List the backups of the app database, newest first
+#[tauri::command]
+pub async fn app_list_backups(app: AppHandle) -> CmdResult<Vec<Backup>> {
+    let db_path = database_path(&app)?;
+    let backups = list_backups(&backups_dir(&db_path))
+        .await
+        .context("failed to list backups")?;
+    Ok(backups)
+}
+
+/// Restore the app database from the backup `name` and restart the app. The
+/// current database is backed up first so the restore can be undone
+#[tauri::command]
+pub async fn app_restore_backup(
+    app: AppHandle,
+    db: State<'_, DbPool>,
+    name: String,
+) -> CmdResult<()> {
+    let db_path = database_path(&app)?;
+    let backups_dir = backups_dir(&db_path);
+
+    let exists = backup_exists(&backups_dir, &name)
+        .await
+        .context("failed to list backups")?;
+    if !exists {
+        return Err(CmdError::coded(
+            eyre::eyre!("backup not found"),
+            "BACKUP_NOT_FOUND",
+        ));
+    }
+
+    create_backup(db.deref(), &backups_dir)
+        .await
+        .context("failed to back up current database")?;
+
+    // The database must be closed before its file is replaced
+    db.close().await;
+
+    // The closed database cannot be used again so the app restarts either way
+    if let Err(error) = restore_backup(&db_path, &name).await {
+        tracing::error!(?error, "failed to restore database backup");
+        app.restart();
+    }
+
+    // Pruned after restoring as the restored backup may be the oldest
+    if let Err(error) = prune_backups(&backups_dir).await {
+        tracing::warn!(?error, "failed to remove old database backups");
+    }
+
+    tracing::info!(backup = name, "restored database backup, restarting");
+    app.restart();
+}
+
+/// Check that the app database was migrated at startup, returns the
+/// migration error otherwise so a backup can be restored
+#[tauri::command]
+pub async fn app_check_migrations(status: State<'_, MigrationStatus>) -> CmdResult<()> {
+    match status.error() {
+        Some(error) => {
+            let code = error.code();
+            Err(CmdError::coded(error, code))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Revert the app database migration `name` and every migration applied
+/// after it, used before downgrading to a version of the app without the
+/// migration. The database is backed up first and the app exits afterwards
+/// as this version of the app cannot use the reverted database
+#[tauri::command]
+pub async fn app_rollback_migration(
+    app: AppHandle,
+    db: State<'_, DbPool>,
+    name: String,
+) -> CmdResult<()> {
+    let db_path = database_path(&app)?;
+    let backups_dir = backups_dir(&db_path);
+
+    create_backup(db.deref(), &backups_dir)
+        .await
+        .context("failed to back up current database")?;
+
+    if let Err(error) = prune_backups(&backups_dir).await {
+        tracing::warn!(?error, "failed to remove old database backups");
+    }
+
+    if let Err(error) = rollback(db.deref(), &name).await {
+        let code = error.code();
+        return Err(CmdError::coded(error, code));
+    }
+
+    tracing::info!(migration = name, "reverted database migrations, exiting");
+    db.close().await;
+    app.exit(0);
+
+    Ok(())
+}
+
+fn database_path(app: &AppHandle) -> CmdResult<PathBuf> {
+    let app_data_path = app
+        .path()
+        .app_data_dir()
+        .context("failed to get app data dir")?;
+    Ok(app_data_path.join(DATABASE_FILE_NAME))
+}
@@ -0,0 +1,100 @@
+use std::{path::PathBuf, sync::Arc};
+
+use eyre::{Context, ContextCompat};
+use tauri::State;
+use uuid::Uuid;
+
+use crate::{
+    commands::CmdResult,
+    gateway::{har::Har, history::GatewayExchange, Gateway},
+};
+
+/// Get the recent gateway exchanges for a server
+#[tauri::command]
+pub async fn gateway_get_history(
+    gateway: State<'_, Arc<Gateway>>,
+    server_id: Uuid,
+) -> CmdResult<Vec<GatewayExchange>> {
+    let history = gateway.history().get(server_id).await;
+    Ok(history)
+}
+
+/// Clear the gateway exchange history for a server
+#[tauri::command]
+pub async fn gateway_clear_history(
+    gateway: State<'_, Arc<Gateway>>,
+    server_id: Uuid,
+) -> CmdResult<()> {
+    gateway.history().clear(server_id).await;
+    Ok(())
+}
+
+/// Cancel an in-flight gateway request by the ID sent in the
+/// `x-gateway-request-id` header, returns whether the request was found
+#[tauri::command]
+pub async fn gateway_cancel(gateway: State<'_, Arc<Gateway>>, request_id: Uuid) -> CmdResult<bool> {
+    Ok(gateway.cancel(request_id).await)
+}
+
+/// Start recording gateway traffic for a server into a HAR archive
+#[tauri::command]
+pub async fn gateway_har_start_recording(
+    gateway: State<'_, Arc<Gateway>>,
+    server_id: Uuid,
+) -> CmdResult<()> {
+    gateway.har().start_recording(server_id).await;
+    Ok(())
+}
+
+/// Stop recording gateway traffic for a server and write the HAR archive
+/// to `path`, returns the number of recorded entries
+#[tauri::command]
+pub async fn gateway_har_stop_recording(
+    gateway: State<'_, Arc<Gateway>>,
+    server_id: Uuid,
+    path: PathBuf,
+) -> CmdResult<usize> {
+    let har = gateway
+        .har()
+        .stop_recording(server_id)
+        .await
+        .context("server is not being recorded")?;
+
+    let entries = har.log.entries.len();
+    let data = serde_json::to_vec_pretty(&har)?;
+
+    tokio::fs::write(&path, data)
+        .await
+        .context("failed to write har file")?;
+
+    Ok(entries)
+}
+
+/// Start answering gateway requests for a server from the HAR archive at
+/// `path`, returns the number of loaded entries
+#[tauri::command]
+pub async fn gateway_har_start_replay(
+    gateway: State<'_, Arc<Gateway>>,
+    server_id: Uuid,
+    path: PathBuf,
+) -> CmdResult<usize> {
+    let data = tokio::fs::read(&path)
+        .await
+        .context("failed to read har file")?;
+    let har: Har = serde_json::from_slice(&data).context("failed to parse har file")?;
+
+    let entries = har.log.entries.len();
+    gateway.har().start_replay(server_id, har).await;
+
+    Ok(entries)
+}
+
+/// Stop replaying a HAR archive for a server
+#[tauri::command]
+pub async fn gateway_har_stop_replay(
+    gateway: State<'_, Arc<Gateway>>,
+    server_id: Uuid,
+) -> CmdResult<()> {
+    gateway.har().stop_replay(server_id).await;
+    Ok(())
+}
@@ -1,12 +1,29 @@
 use serde::Serialize;
+use tauri::{ipc::Invoke, Manager, Runtime};
 
+use crate::{database::migrations::MigrationStatus, server::ActiveServer};
+
+pub mod app;
+pub mod gateway;
 pub mod root;
 pub mod server;
 pub mod tenant;
 pub mod utils;
+pub mod vault;
 
 type CmdResult<T> = Result<T, CmdError>;
 
+/// Commands that remain available when the app database failed to migrate,
+/// these allow the UI to report the failure and restore a backup
+const RECOVERY_COMMANDS: &[&str] = &[
+    "app_check_migrations",
+    "app_list_backups",
+    "app_restore_backup",
+    "app_rollback_migration",
+    "app_lock",
+    "app_report_activity",
+];
+
 /// Error output from a handler
 #[derive(Debug, Serialize)]
 pub struct CmdError {
@@ -36,3 +53,41 @@ where
         }
     }
 }
+
+/// Ensure a destructive command is allowed to run against the server
+pub fn ensure_writable(server: &ActiveServer) -> CmdResult<()> {
+    if server.is_read_only() {
+        return Err(CmdError::coded(
+            eyre::eyre!("server is read only"),
+            "READ_ONLY",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Wrap the command handler so that commands other than the recovery commands
+/// fail with the migration error while the app database is not migrated,
+/// rather than running against a partially migrated schema
+pub fn require_migrated<R, F>(handler: F) -> impl Fn(Invoke<R>) -> bool + Send + Sync + 'static
+where
+    R: Runtime,
+    F: Fn(Invoke<R>) -> bool + Send + Sync + 'static,
+{
+    move |invoke| {
+        if !RECOVERY_COMMANDS.contains(&invoke.message.command()) {
+            let webview = invoke.message.webview();
+            let error = webview
+                .try_state::<MigrationStatus>()
+                .and_then(|status| status.error());
+
+            if let Some(error) = error {
+                let code = error.code();
+                invoke.resolver.reject(CmdError::coded(error, code));
+                return true;
+            }
+        }
+
+        handler(invoke)
+    }
+}
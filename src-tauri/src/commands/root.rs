@@ -8,7 +8,10 @@ use serde::Serialize;
 use tauri::State;
 use uuid::Uuid;
 
-use crate::{commands::CmdResult, server::ServerStore};
+use crate::{
+    commands::{ensure_writable, CmdResult},
+    server::ServerStore,
+};
 
 /// Check if the provided server is initialized
 #[tauri::command]
@@ -36,6 +39,9 @@ pub async fn root_initialize(
         .get_server(server_id)
         .await
         .context("server not found")?;
+
+    ensure_writable(&server)?;
+
     docbox_management::root::initialize::initialize(
         &server.db_provider,
         &server.secrets,
@@ -43,7 +49,7 @@ pub async fn root_initialize(
     )
     .await?;
 
-    todo!()
+    Ok(())
 }
 
 #[derive(Serialize)]
@@ -96,6 +102,8 @@ pub async fn root_apply_migrations(
         .await
         .context("server not found")?;
 
+    ensure_writable(&server)?;
+
     let outcome =
         docbox_management::tenant::migrate_tenants::migrate_tenants(&server.db_provider, config)
             .await?;
@@ -1,27 +1,73 @@
-use std::{ops::Deref, sync::Arc};
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use aws_config::SdkConfig;
 use eyre::{Context, ContextCompat};
 use tauri::State;
 use uuid::Uuid;
+use zeroize::Zeroizing;
 
 use crate::{
-    commands::{CmdError, CmdResult},
-    database::entity::server::{CreateServer, Server, ServerId},
-    server::{LoadServerError, ServerStore},
+    commands::{vault::vault_error, CmdError, CmdResult},
+    database::entity::server::{CreateServer, Server, ServerConfig, ServerId, UpdateServer},
+    server::{
+        bundle::{BundleError, ImportCandidate, ImportOutcome, ImportResolution, ServerBundle},
+        health::ServerHealth,
+        lock::IdleLock,
+        validate::{validate_server, ConfigValidation},
+        vault::{Vault, VaultError},
+        ActiveServer, LoadServerConfig, LoadServerError, ServerStore,
+    },
+    utils::{
+        encryption::{decrypt, encrypt, upgrade, DecryptError, EncryptedContent},
+        key_file::{read_key_file, KeyFile},
+        secret::SecretString,
+    },
 };
 
 /// Create a server
 #[tauri::command]
 pub async fn server_create(
     db: State<'_, crate::database::DbPool>,
-    create: CreateServer,
+    server_store: State<'_, Arc<ServerStore>>,
+    mut create: CreateServer,
 ) -> CmdResult<Server> {
+    // Plain configs are stored in the vault when it is enabled
+    if let ServerConfig::Config { data } = &create.config {
+        if Vault::is_enabled(db.deref()).await? {
+            create.config = server_store.vault().seal(data).map_err(vault_error)?;
+        }
+    }
+
     let server = Server::create(db.deref(), create).await?;
 
     Ok(server)
 }
 
+/// Validate a server without storing it, performs a trial load and
+/// connectivity checks
+#[tauri::command]
+pub async fn server_validate_config(
+    server_store: State<'_, Arc<ServerStore>>,
+    sdk_config: State<'_, SdkConfig>,
+    create: CreateServer,
+    password: Option<SecretString>,
+    key_file: Option<PathBuf>,
+) -> CmdResult<ConfigValidation> {
+    Ok(validate_server(
+        &sdk_config,
+        server_store.vault(),
+        create,
+        password,
+        key_file,
+    )
+    .await)
+}
+
 /// Get all servers
 #[tauri::command]
 pub async fn server_get_all(db: State<'_, crate::database::DbPool>) -> CmdResult<Vec<Server>> {
@@ -36,14 +82,26 @@ pub async fn server_load(
     db: State<'_, crate::database::DbPool>,
     server_store: State<'_, Arc<ServerStore>>,
     sdk_config: State<'_, SdkConfig>,
+    idle_lock: State<'_, Arc<IdleLock>>,
     server_id: Uuid,
     load_config: crate::server::LoadServerConfig,
 ) -> CmdResult<()> {
+    // Loading a server restarts the idle period so it is not locked right away
+    idle_lock.touch();
+
     let server = Server::find_by_id(db.deref(), server_id)
         .await?
         .context("server not found")?;
 
-    let _server = match server_store
+    // Content encrypted with outdated parameters is re-wrapped once loaded
+    let outdated = match &server.config {
+        ServerConfig::Encrypted(content) if content.needs_upgrade() => Some(content.clone()),
+        _ => None,
+    };
+    let password = load_config.password.clone();
+    let key_file = load_config.key_file.clone();
+
+    let server = match server_store
         .try_load_server(&sdk_config, server, load_config)
         .await
     {
@@ -54,11 +112,96 @@ pub async fn server_load(
                 LoadServerError::IncorrectPassword => {
                     Err(CmdError::coded(error, "INCORRECT_PASSWORD"))
                 }
+                LoadServerError::MissingKeyFile => Err(CmdError::coded(error, "MISSING_KEY_FILE")),
+                LoadServerError::ReadKeyFile(_) => Err(CmdError::coded(error, "INVALID_KEY_FILE")),
+                LoadServerError::Vault(VaultError::Locked) => {
+                    Err(CmdError::coded(error, "VAULT_LOCKED"))
+                }
                 _ => Err(CmdError::coded(error, "OTHER")),
             }
         }
     };
 
+    if let (Some(content), Some(password)) = (outdated, password) {
+        if let Err(error) = upgrade_server_config(
+            db.deref(),
+            &server,
+            &content,
+            &password,
+            key_file.as_deref(),
+        )
+        .await
+        {
+            tracing::warn!(?error, %server_id, "failed to upgrade encrypted server config");
+        }
+    }
+
+    Ok(())
+}
+
+/// Re-encrypt the config of a loaded server that was encrypted with
+/// outdated encryption parameters
+async fn upgrade_server_config(
+    db: &crate::database::DbPool,
+    server: &ActiveServer,
+    content: &EncryptedContent,
+    password: &str,
+    key_file: Option<&Path>,
+) -> eyre::Result<()> {
+    let key_file = match key_file.filter(|_| content.key_file) {
+        Some(path) => Some(read_key_file(path).await?),
+        None => None,
+    };
+
+    let data = Zeroizing::new(serde_json::to_vec(&server.config)?);
+    let password = SecretString::new(password.to_string());
+    let content = content.clone();
+
+    // Key derivation is CPU bound so it is kept off the async runtime
+    let upgraded = tokio::task::spawn_blocking(move || {
+        let key_file = key_file.as_deref().map(Vec::as_slice);
+        upgrade(password.as_bytes(), key_file, &content, &data)
+    })
+    .await??;
+
+    let Some(content) = upgraded else {
+        return Ok(());
+    };
+
+    let update = UpdateServer {
+        name: None,
+        config: Some(ServerConfig::Encrypted(content)),
+    };
+
+    Server::update(db, server.id, &update).await?;
+    Ok(())
+}
+
+/// Check the password and key file of an encrypted server without loading
+/// it, fails with `INCORRECT_PASSWORD` when the password is incorrect
+#[tauri::command]
+pub async fn server_verify_password(
+    db: State<'_, crate::database::DbPool>,
+    server_id: Uuid,
+    password: SecretString,
+    key_file: Option<PathBuf>,
+) -> CmdResult<()> {
+    let key_file = load_key_file(key_file.as_deref()).await?;
+    let server = Server::find_by_id(db.deref(), server_id)
+        .await?
+        .context("server not found")?;
+
+    // Key derivation is CPU bound so it is kept off the async runtime
+    tokio::task::spawn_blocking(move || {
+        decrypt_server_config(
+            &server.config,
+            &password,
+            key_file.as_deref().map(Vec::as_slice),
+        )
+    })
+    .await
+    .context("failed to verify password")??;
+
     Ok(())
 }
 
@@ -83,6 +226,32 @@ pub async fn server_is_active(
     Ok(server.is_some())
 }
 
+/// Check if a loaded server has certificate verification disabled
+#[tauri::command]
+pub async fn server_is_insecure(
+    server_store: State<'_, Arc<ServerStore>>,
+    server_id: Uuid,
+) -> CmdResult<bool> {
+    let server = server_store
+        .get_server(server_id)
+        .await
+        .context("server not found")?;
+    Ok(server.accepts_invalid_certificates())
+}
+
+/// Check the health of each component of a loaded server
+#[tauri::command]
+pub async fn server_health_check(
+    server_store: State<'_, Arc<ServerStore>>,
+    server_id: Uuid,
+) -> CmdResult<ServerHealth> {
+    let server = server_store
+        .get_server(server_id)
+        .await
+        .context("server not found")?;
+    Ok(server.health_check().await)
+}
+
 /// Get a list of currently active servers
 #[tauri::command]
 pub async fn server_get_active(
@@ -92,6 +261,417 @@ pub async fn server_get_active(
     Ok(server.into_iter().map(|server| server.id).collect())
 }
 
+/// Update the name and/or config of a server
+///
+/// Plain configs provided for an encrypted server are re-encrypted with
+/// `password` and `key_file`, which must match the current password and key
+/// file. Other plain configs are stored in the vault when it is enabled. A
+/// loaded server is reloaded with the new config or unloaded if it can no
+/// longer be loaded
+#[tauri::command]
+pub async fn server_update(
+    db: State<'_, crate::database::DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+    sdk_config: State<'_, SdkConfig>,
+    server_id: Uuid,
+    mut update: UpdateServer,
+    password: Option<SecretString>,
+    key_file: Option<PathBuf>,
+) -> CmdResult<()> {
+    let server = Server::find_by_id(db.deref(), server_id)
+        .await?
+        .context("server not found")?;
+
+    match (update.config.as_ref(), &server.config) {
+        (Some(ServerConfig::Config { data }), ServerConfig::Encrypted(content)) => {
+            let password = password.as_deref().ok_or_else(|| {
+                CmdError::coded(LoadServerError::MissingPassword, "MISSING_PASSWORD")
+            })?;
+
+            let key = load_key_file(key_file.as_deref()).await?;
+            let key = key.as_deref().map(Vec::as_slice);
+
+            // Only checks the current password and key file are correct
+            decrypt_server_config(&server.config, password, key)?;
+
+            let data = Zeroizing::new(serde_json::to_vec(data)?);
+            let key = key.filter(|_| content.key_file);
+            update.config = Some(ServerConfig::Encrypted(encrypt(
+                password.as_bytes(),
+                key,
+                &data,
+            )?));
+        }
+        // Plain configs are stored in the vault when it is enabled
+        (Some(ServerConfig::Config { data }), _) => {
+            if Vault::is_enabled(db.deref()).await? {
+                update.config = Some(server_store.vault().seal(data).map_err(vault_error)?);
+            }
+        }
+        _ => {}
+    }
+
+    Server::update(db.deref(), server_id, &update).await?;
+
+    // Reload the active server so it uses the new config
+    if update.config.is_some() && server_store.get_server(server_id).await.is_some() {
+        let server = Server::find_by_id(db.deref(), server_id)
+            .await?
+            .context("server not found")?;
+
+        if let Err(error) = server_store
+            .try_load_server(&sdk_config, server, LoadServerConfig { password, key_file })
+            .await
+        {
+            tracing::warn!(?error, %server_id, "failed to reload updated server, unloading");
+            server_store.remove_server(server_id).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Change the password of an encrypted server, the config is re-encrypted
+/// with a new salt and nonce. A required key file remains required
+#[tauri::command]
+pub async fn server_change_password(
+    db: State<'_, crate::database::DbPool>,
+    server_id: Uuid,
+    old_password: SecretString,
+    new_password: SecretString,
+    key_file: Option<PathBuf>,
+) -> CmdResult<()> {
+    let key_file = load_key_file(key_file.as_deref()).await?;
+    let server = Server::find_by_id(db.deref(), server_id)
+        .await?
+        .context("server not found")?;
+
+    // Key derivation is CPU bound so it is kept off the async runtime
+    let config = server.config.clone();
+    let content = tokio::task::spawn_blocking(move || {
+        let key_file = key_file.as_deref().map(Vec::as_slice);
+        let data = decrypt_server_config(&config, &old_password, key_file)?;
+        let key_file = key_file.filter(|_| requires_key_file(&config));
+        CmdResult::Ok(encrypt(new_password.as_bytes(), key_file, &data)?)
+    })
+    .await
+    .context("failed to change password")??;
+
+    replace_server_config(
+        db.deref(),
+        server_id,
+        &server.config,
+        ServerConfig::Encrypted(content),
+    )
+    .await
+}
+
+/// Encrypt the plain config of a server using `password`, the key file is
+/// also required to decrypt the config when `key_file` is provided
+#[tauri::command]
+pub async fn server_encrypt_config(
+    db: State<'_, crate::database::DbPool>,
+    server_id: Uuid,
+    password: SecretString,
+    key_file: Option<PathBuf>,
+) -> CmdResult<()> {
+    let key_file = load_key_file(key_file.as_deref()).await?;
+    let server = Server::find_by_id(db.deref(), server_id)
+        .await?
+        .context("server not found")?;
+
+    let ServerConfig::Config { data } = &server.config else {
+        return Err(CmdError::coded(
+            eyre::eyre!("server config is not a plain config"),
+            "INVALID_CONFIG_TYPE",
+        ));
+    };
+
+    // Key derivation is CPU bound so it is kept off the async runtime
+    let data = Zeroizing::new(serde_json::to_vec(data)?);
+    let content = tokio::task::spawn_blocking(move || {
+        encrypt(
+            password.as_bytes(),
+            key_file.as_deref().map(Vec::as_slice),
+            &data,
+        )
+    })
+    .await
+    .context("failed to encrypt config")??;
+
+    replace_server_config(
+        db.deref(),
+        server_id,
+        &server.config,
+        ServerConfig::Encrypted(content),
+    )
+    .await
+}
+
+/// Remove the password from an encrypted server, storing its config as a
+/// plain config or in the vault when the vault is enabled
+#[tauri::command]
+pub async fn server_decrypt_config(
+    db: State<'_, crate::database::DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+    server_id: Uuid,
+    password: SecretString,
+    key_file: Option<PathBuf>,
+) -> CmdResult<()> {
+    let key_file = load_key_file(key_file.as_deref()).await?;
+    let server = Server::find_by_id(db.deref(), server_id)
+        .await?
+        .context("server not found")?;
+
+    // Key derivation is CPU bound so it is kept off the async runtime
+    let config = server.config.clone();
+    let data = tokio::task::spawn_blocking(move || {
+        decrypt_server_config(&config, &password, key_file.as_deref().map(Vec::as_slice))
+    })
+    .await
+    .context("failed to decrypt config")??;
+    let data = serde_json::from_slice(&data)?;
+
+    let config = if Vault::is_enabled(db.deref()).await? {
+        server_store.vault().seal(&data).map_err(vault_error)?
+    } else {
+        ServerConfig::Config { data }
+    };
+
+    replace_server_config(db.deref(), server_id, &server.config, config).await
+}
+
+/// Add, replace or remove the key file required by an encrypted server.
+/// `key_file` is the currently required key file and `new_key_file` the key
+/// file to require from now on, [None] removes the requirement
+#[tauri::command]
+pub async fn server_set_key_file(
+    db: State<'_, crate::database::DbPool>,
+    server_id: Uuid,
+    password: SecretString,
+    key_file: Option<PathBuf>,
+    new_key_file: Option<PathBuf>,
+) -> CmdResult<()> {
+    let key_file = load_key_file(key_file.as_deref()).await?;
+    let new_key_file = load_key_file(new_key_file.as_deref()).await?;
+
+    let server = Server::find_by_id(db.deref(), server_id)
+        .await?
+        .context("server not found")?;
+
+    // Key derivation is CPU bound so it is kept off the async runtime
+    let config = server.config.clone();
+    let content = tokio::task::spawn_blocking(move || {
+        let data =
+            decrypt_server_config(&config, &password, key_file.as_deref().map(Vec::as_slice))?;
+        CmdResult::Ok(encrypt(
+            password.as_bytes(),
+            new_key_file.as_deref().map(Vec::as_slice),
+            &data,
+        )?)
+    })
+    .await
+    .context("failed to set key file")??;
+
+    replace_server_config(
+        db.deref(),
+        server_id,
+        &server.config,
+        ServerConfig::Encrypted(content),
+    )
+    .await
+}
+
+/// Replace the config of a server that was read as `previous`, failing with
+/// `CONFIG_CHANGED` if the config was changed in the meantime. Keys are
+/// derived before calling this so the transaction only covers the write
+async fn replace_server_config(
+    db: &crate::database::DbPool,
+    server_id: ServerId,
+    previous: &ServerConfig,
+    config: ServerConfig,
+) -> CmdResult<()> {
+    let mut tx = db.begin().await?;
+    let server = Server::find_by_id(&mut *tx, server_id)
+        .await?
+        .context("server not found")?;
+
+    if serde_json::to_value(&server.config)? != serde_json::to_value(previous)? {
+        return Err(CmdError::coded(
+            eyre::eyre!("server config was changed, try again"),
+            "CONFIG_CHANGED",
+        ));
+    }
+
+    let update = UpdateServer {
+        name: None,
+        config: Some(config),
+    };
+
+    Server::update(&mut *tx, server_id, &update).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Decrypt the serialized config data of an encrypted server config
+fn decrypt_server_config(
+    config: &ServerConfig,
+    password: &str,
+    key_file: Option<&[u8]>,
+) -> CmdResult<Zeroizing<Vec<u8>>> {
+    let ServerConfig::Encrypted(content) = config else {
+        return Err(CmdError::coded(
+            eyre::eyre!("server config is not encrypted"),
+            "INVALID_CONFIG_TYPE",
+        ));
+    };
+
+    decrypt(password.as_bytes(), key_file, content)
+        .map(Zeroizing::new)
+        .map_err(|error| match error {
+            DecryptError::MissingKeyFile => {
+                CmdError::coded(LoadServerError::MissingKeyFile, "MISSING_KEY_FILE")
+            }
+            _ => CmdError::coded(LoadServerError::IncorrectPassword, "INCORRECT_PASSWORD"),
+        })
+}
+
+/// Check if a server config requires a key file to decrypt
+fn requires_key_file(config: &ServerConfig) -> bool {
+    matches!(config, ServerConfig::Encrypted(content) if content.key_file)
+}
+
+/// Read the key file at `path` when one is provided
+pub(crate) async fn load_key_file(path: Option<&Path>) -> CmdResult<Option<KeyFile>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+
+    read_key_file(path)
+        .await
+        .map(Some)
+        .map_err(|error| CmdError::coded(LoadServerError::ReadKeyFile(error), "INVALID_KEY_FILE"))
+}
+
+/// Set the display order of servers to the order of `server_ids`, which
+/// must contain every server exactly once
+#[tauri::command]
+pub async fn server_reorder(
+    db: State<'_, crate::database::DbPool>,
+    server_ids: Vec<Uuid>,
+) -> CmdResult<()> {
+    if !Server::reorder(db.deref(), &server_ids).await? {
+        return Err(CmdError::coded(
+            eyre::eyre!("server order must contain every server exactly once"),
+            "INVALID_ORDER",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Export the servers with the provided IDs into a bundle file at `path`
+/// encrypted using `password`, returns the number of exported servers
+#[tauri::command]
+pub async fn server_export(
+    db: State<'_, crate::database::DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+    server_ids: Vec<Uuid>,
+    path: PathBuf,
+    password: SecretString,
+) -> CmdResult<usize> {
+    let servers: Vec<Server> = Server::all(db.deref())
+        .await?
+        .into_iter()
+        .filter(|server| server_ids.contains(&server.id))
+        .map(|mut server| {
+            // Vault configs are only readable on this device, export them as
+            // plain configs protected by the bundle password
+            if let ServerConfig::Vault { nonce, data } = &server.config {
+                let data = server_store
+                    .vault()
+                    .open(nonce, data)
+                    .map_err(vault_error)?;
+                server.config = ServerConfig::Config { data };
+            }
+
+            Ok(server)
+        })
+        .collect::<CmdResult<_>>()?;
+
+    let exported = servers.len();
+    let data = ServerBundle::from_servers(servers).encrypt(&password)?;
+
+    tokio::fs::write(&path, data)
+        .await
+        .context("failed to write server bundle")?;
+
+    Ok(exported)
+}
+
+/// Read the servers from a bundle file along with any conflicts with the
+/// existing servers, nothing is imported
+#[tauri::command]
+pub async fn server_import_preview(
+    db: State<'_, crate::database::DbPool>,
+    path: PathBuf,
+    password: SecretString,
+) -> CmdResult<Vec<ImportCandidate>> {
+    let bundle = read_bundle(&path, &password).await?;
+    let existing = Server::all(db.deref()).await?;
+
+    Ok(bundle.candidates(&existing))
+}
+
+/// Import the servers from a bundle file, conflicting servers are handled
+/// according to `resolutions` and skipped when no resolution is provided
+#[tauri::command]
+pub async fn server_import(
+    db: State<'_, crate::database::DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+    path: PathBuf,
+    password: SecretString,
+    resolutions: HashMap<Uuid, ImportResolution>,
+) -> CmdResult<ImportOutcome> {
+    let mut bundle = read_bundle(&path, &password).await?;
+
+    // Plain configs are stored in the vault when it is enabled
+    if Vault::is_enabled(db.deref()).await? {
+        for server in &mut bundle.servers {
+            if let ServerConfig::Config { data } = &server.config {
+                server.config = server_store.vault().seal(data).map_err(vault_error)?;
+            }
+        }
+    }
+
+    let outcome = bundle
+        .import(db.deref(), resolutions)
+        .await
+        .map_err(|error| match error {
+            BundleError::InvalidRename(_) => CmdError::coded(error, "INVALID_NAME"),
+            error => CmdError::from(error),
+        })?;
+
+    // Replaced servers must be loaded again using their new config
+    for server_id in &outcome.replaced {
+        server_store.remove_server(*server_id).await;
+    }
+
+    Ok(outcome)
+}
+
+async fn read_bundle(path: &Path, password: &str) -> CmdResult<ServerBundle> {
+    let data = tokio::fs::read(path)
+        .await
+        .context("failed to read server bundle")?;
+
+    ServerBundle::decrypt(&data, password).map_err(|error| match error {
+        BundleError::IncorrectPassword => CmdError::coded(error, "INCORRECT_PASSWORD"),
+        error => CmdError::coded(error, "INVALID_BUNDLE"),
+    })
+}
+
 /// Delete a server
 #[tauri::command]
 pub async fn server_delete(
@@ -107,3 +687,21 @@ pub async fn server_delete(
 
     Ok(())
 }
+
+/// Set whether a server is read only
+#[tauri::command]
+pub async fn server_set_read_only(
+    db: State<'_, crate::database::DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+    server_id: Uuid,
+    read_only: bool,
+) -> CmdResult<()> {
+    Server::set_read_only(db.deref(), server_id, read_only).await?;
+
+    // Apply to the loaded server
+    if let Some(server) = server_store.get_server(server_id).await {
+        server.set_read_only(read_only);
+    }
+
+    Ok(())
+}
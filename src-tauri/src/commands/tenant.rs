@@ -6,7 +6,10 @@ use eyre::ContextCompat;
 use tauri::State;
 use uuid::Uuid;
 
-use crate::{commands::CmdResult, server::ServerStore};
+use crate::{
+    commands::{ensure_writable, CmdResult},
+    server::ServerStore,
+};
 
 /// Create a tenant
 #[tauri::command]
@@ -20,6 +23,8 @@ pub async fn tenant_create(
         .await
         .context("server not found")?;
 
+    ensure_writable(&server)?;
+
     let tenant = docbox_management::tenant::create_tenant::create_tenant(
         &server.db_provider,
         &server.search,
@@ -81,6 +86,8 @@ pub async fn tenant_delete(
         .await
         .context("server not found")?;
 
+    ensure_writable(&server)?;
+
     docbox_management::tenant::delete_tenant::delete_tenant(&server.db_provider, &env, tenant_id)
         .await?;
 
@@ -101,6 +108,8 @@ pub async fn tenant_migrate(
         .await
         .context("server not found")?;
 
+    ensure_writable(&server)?;
+
     let tenant =
         docbox_management::tenant::get_tenant::get_tenant(&server.db_provider, &env, tenant_id)
             .await?
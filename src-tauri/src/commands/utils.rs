@@ -1,11 +1,37 @@
+use std::path::PathBuf;
+
+use eyre::Context;
+
 use crate::{
-    commands::CmdResult,
-    utils::encryption::{encrypt, EncryptedContent},
+    commands::{server::load_key_file, CmdResult},
+    utils::{
+        encryption::{encrypt, EncryptedContent},
+        key_file::generate_key_file,
+        secret::SecretString,
+    },
 };
 
 /// Check if the provided server is initialized
 #[tauri::command]
-pub fn utils_encrypt(password: String, input: String) -> CmdResult<EncryptedContent> {
-    let output = encrypt(password.as_bytes(), input.as_bytes())?;
+pub async fn utils_encrypt(
+    password: SecretString,
+    input: SecretString,
+    key_file: Option<PathBuf>,
+) -> CmdResult<EncryptedContent> {
+    let key_file = load_key_file(key_file.as_deref()).await?;
+    let output = encrypt(
+        password.as_bytes(),
+        key_file.as_deref().map(Vec::as_slice),
+        input.as_bytes(),
+    )?;
     Ok(output)
 }
+
+/// Generate a new random key file at `path`, fails if the file already exists
+#[tauri::command]
+pub async fn utils_generate_key_file(path: PathBuf) -> CmdResult<()> {
+    generate_key_file(&path)
+        .await
+        .context("failed to generate key file")?;
+    Ok(())
+}
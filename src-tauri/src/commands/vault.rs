@@ -0,0 +1,229 @@
+use std::{collections::HashMap, ops::Deref, path::PathBuf, sync::Arc};
+
+use eyre::ContextCompat;
+use serde::Serialize;
+use tauri::State;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::{
+    commands::{server::load_key_file, CmdError, CmdResult},
+    database::{
+        entity::server::{Server, ServerConfig, ServerConfigData, ServerId, UpdateServer},
+        DbPool,
+    },
+    server::{
+        vault::{Vault, VaultError},
+        ServerStore,
+    },
+    utils::{
+        encryption::{decrypt, encrypt, DecryptError},
+        secret::SecretString,
+    },
+};
+
+/// Convert a vault error into a coded command error
+pub(crate) fn vault_error(error: VaultError) -> CmdError {
+    let code = match &error {
+        VaultError::NotEnabled => "VAULT_NOT_ENABLED",
+        VaultError::AlreadyEnabled => "VAULT_ALREADY_ENABLED",
+        VaultError::Locked => "VAULT_LOCKED",
+        VaultError::IncorrectPassword => "INCORRECT_PASSWORD",
+        _ => "OTHER",
+    };
+
+    CmdError::coded(error, code)
+}
+
+#[derive(Serialize)]
+pub struct VaultStatus {
+    pub enabled: bool,
+    pub unlocked: bool,
+}
+
+/// Get whether the vault is enabled and unlocked
+#[tauri::command]
+pub async fn vault_status(
+    db: State<'_, DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+) -> CmdResult<VaultStatus> {
+    Ok(VaultStatus {
+        enabled: Vault::is_enabled(db.deref()).await?,
+        unlocked: server_store.vault().is_unlocked(),
+    })
+}
+
+/// Enable the vault protected by a master password
+#[tauri::command]
+pub async fn vault_create(
+    db: State<'_, DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+    password: SecretString,
+) -> CmdResult<()> {
+    server_store
+        .vault()
+        .create(db.deref(), &password)
+        .await
+        .map_err(vault_error)
+}
+
+/// Unlock the vault for the session
+#[tauri::command]
+pub async fn vault_unlock(
+    db: State<'_, DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+    password: SecretString,
+) -> CmdResult<()> {
+    server_store
+        .vault()
+        .unlock(db.deref(), &password)
+        .await
+        .map_err(vault_error)
+}
+
+/// Lock the vault, servers loaded from the vault remain loaded until the
+/// app locks
+#[tauri::command]
+pub fn vault_lock(server_store: State<'_, Arc<ServerStore>>) {
+    server_store.vault().lock();
+}
+
+/// Disable the vault, all servers must be moved out of the vault first
+#[tauri::command]
+pub async fn vault_disable(
+    db: State<'_, DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+) -> CmdResult<()> {
+    let in_vault = Server::all(db.deref())
+        .await?
+        .iter()
+        .any(|server| matches!(server.config, ServerConfig::Vault { .. }));
+
+    if in_vault {
+        return Err(CmdError::coded(
+            eyre::eyre!("servers must be moved out of the vault before it is disabled"),
+            "VAULT_NOT_EMPTY",
+        ));
+    }
+
+    server_store
+        .vault()
+        .disable(db.deref())
+        .await
+        .map_err(vault_error)
+}
+
+/// Move servers into the vault, encrypted servers require their password
+/// in `passwords` and their key file in `key_files` when one is required.
+/// AWS secret servers are not stored locally and are left
+/// unchanged. Returns the IDs of the moved servers
+#[tauri::command]
+pub async fn vault_migrate_in(
+    db: State<'_, DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+    server_ids: Vec<Uuid>,
+    passwords: HashMap<Uuid, SecretString>,
+    key_files: Option<HashMap<Uuid, PathBuf>>,
+) -> CmdResult<Vec<ServerId>> {
+    let key_files = key_files.unwrap_or_default();
+    let vault = server_store.vault();
+    let mut migrated = Vec::new();
+    let mut tx = db.begin().await?;
+
+    for server_id in server_ids {
+        let server = Server::find_by_id(&mut *tx, server_id)
+            .await?
+            .context("server not found")?;
+
+        let data: ServerConfigData = match server.config {
+            ServerConfig::Config { data } => data,
+            ServerConfig::Encrypted(content) => {
+                let password = passwords.get(&server_id).ok_or_else(|| {
+                    CmdError::coded(
+                        eyre::eyre!("missing password for server {}", server.name),
+                        "MISSING_PASSWORD",
+                    )
+                })?;
+
+                let key_file =
+                    load_key_file(key_files.get(&server_id).map(PathBuf::as_path)).await?;
+                let key_file = key_file.as_deref().map(Vec::as_slice);
+
+                let decrypted = decrypt(password.as_bytes(), key_file, &content)
+                    .map(Zeroizing::new)
+                    .map_err(|error| match error {
+                        DecryptError::MissingKeyFile => CmdError::coded(
+                            eyre::eyre!("missing key file for server {}", server.name),
+                            "MISSING_KEY_FILE",
+                        ),
+                        _ => CmdError::coded(
+                            eyre::eyre!("incorrect password for server {}", server.name),
+                            "INCORRECT_PASSWORD",
+                        ),
+                    })?;
+
+                serde_json::from_slice(&decrypted)?
+            }
+            ServerConfig::AwsSecret { .. } | ServerConfig::Vault { .. } => continue,
+        };
+
+        let update = UpdateServer {
+            name: None,
+            config: Some(vault.seal(&data).map_err(vault_error)?),
+        };
+
+        Server::update(&mut *tx, server_id, &update).await?;
+        migrated.push(server_id);
+    }
+
+    tx.commit().await?;
+
+    Ok(migrated)
+}
+
+/// Move servers out of the vault, stored as encrypted configs using
+/// `password` when provided otherwise as plain configs. Returns the IDs of
+/// the moved servers
+#[tauri::command]
+pub async fn vault_migrate_out(
+    db: State<'_, DbPool>,
+    server_store: State<'_, Arc<ServerStore>>,
+    server_ids: Vec<Uuid>,
+    password: Option<SecretString>,
+) -> CmdResult<Vec<ServerId>> {
+    let vault = server_store.vault();
+    let mut migrated = Vec::new();
+    let mut tx = db.begin().await?;
+
+    for server_id in server_ids {
+        let server = Server::find_by_id(&mut *tx, server_id)
+            .await?
+            .context("server not found")?;
+
+        let ServerConfig::Vault { nonce, data } = &server.config else {
+            continue;
+        };
+
+        let data = vault.open(nonce, data).map_err(vault_error)?;
+
+        let config = match password.as_deref() {
+            Some(password) => {
+                let data = Zeroizing::new(serde_json::to_vec(&data)?);
+                ServerConfig::Encrypted(encrypt(password.as_bytes(), None, &data)?)
+            }
+            None => ServerConfig::Config { data },
+        };
+
+        let update = UpdateServer {
+            name: None,
+            config: Some(config),
+        };
+
+        Server::update(&mut *tx, server_id, &update).await?;
+        migrated.push(server_id);
+    }
+
+    tx.commit().await?;
+
+    Ok(migrated)
+}
@@ -0,0 +1,139 @@
+//! Snapshots of the app database taken before migrations are applied so
+//! the previous local state can be restored
+
+use std::{
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::fs;
+
+use super::{DbPool, DbResult};
+
+/// Name of the folder next to the database file that holds the backups
+const BACKUPS_DIR: &str = "backups";
+
+/// Number of backups kept, older backups are removed when new ones are made
+const MAX_BACKUPS: usize = 5;
+
+/// Extension of backup files
+const BACKUP_EXTENSION: &str = "db";
+
+#[derive(Debug, Serialize)]
+pub struct Backup {
+    /// File name of the backup
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Size of the backup in bytes
+    pub size: u64,
+}
+
+/// Get the backups folder for the database file at `db_path`
+pub fn backups_dir(db_path: &Path) -> PathBuf {
+    db_path.parent().unwrap_or(Path::new(".")).join(BACKUPS_DIR)
+}
+
+/// Snapshot the database into the backups folder, returns the path of the
+/// created backup
+pub async fn create_backup(db: &DbPool, backups_dir: &Path) -> DbResult<PathBuf> {
+    fs::create_dir_all(backups_dir).await?;
+
+    let name = format!(
+        "app-{}.{BACKUP_EXTENSION}",
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    );
+    let path = backups_dir.join(name);
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid backup file path"))?;
+
+    // VACUUM INTO produces a consistent copy while the database is open
+    sqlx::query("VACUUM INTO ?")
+        .bind(path_str)
+        .execute(db)
+        .await?;
+
+    Ok(path)
+}
+
+/// Remove the oldest backups beyond [MAX_BACKUPS]
+pub async fn prune_backups(backups_dir: &Path) -> io::Result<()> {
+    let backups = list_backups(backups_dir).await?;
+
+    for backup in backups.iter().skip(MAX_BACKUPS) {
+        fs::remove_file(backups_dir.join(&backup.name)).await?;
+    }
+
+    Ok(())
+}
+
+/// List the backups in the backups folder, newest first
+pub async fn list_backups(backups_dir: &Path) -> io::Result<Vec<Backup>> {
+    let mut backups = Vec::new();
+
+    let mut entries = match fs::read_dir(backups_dir).await {
+        Ok(value) => value,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(backups),
+        Err(error) => return Err(error),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension() != Some(OsStr::new(BACKUP_EXTENSION)) {
+            continue;
+        }
+
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        backups.push(Backup {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            created_at: metadata.modified()?.into(),
+            size: metadata.len(),
+        });
+    }
+
+    // Backup names contain their creation time so they sort by age
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+
+    Ok(backups)
+}
+
+/// Check if `name` is one of the listed backups, names that are not listed
+/// such as paths escaping the backups folder are never found
+pub async fn backup_exists(backups_dir: &Path, name: &str) -> io::Result<bool> {
+    let backups = list_backups(backups_dir).await?;
+    Ok(backups.iter().any(|backup| backup.name == name))
+}
+
+/// Replace the database file at `db_path` with the backup `name`, which
+/// must have been checked using [backup_exists]. The database must not be
+/// open while it is replaced
+pub async fn restore_backup(db_path: &Path, name: &str) -> io::Result<()> {
+    let backups_dir = backups_dir(db_path);
+
+    // Copy next to the database first so a failed copy leaves it untouched
+    let mut restore_path = db_path.as_os_str().to_owned();
+    restore_path.push(".restore");
+    fs::copy(backups_dir.join(name), &restore_path).await?;
+
+    // Remove the write-ahead log of the current database so it is not
+    // applied on top of the restored database
+    for suffix in ["-wal", "-shm"] {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(suffix);
+
+        match fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    fs::rename(&restore_path, db_path).await
+}
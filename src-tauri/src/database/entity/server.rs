@@ -1,4 +1,9 @@
-use crate::database::{DbExecutor, DbResult};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{
+    database::{DbExecutor, DbPool, DbResult},
+    utils::{encryption::EncryptedContent, secret::SecretString},
+};
 use docbox_database::DbErr;
 use docbox_search::SearchIndexFactoryConfig;
 use docbox_secrets::SecretsManagerConfig;
@@ -21,6 +26,8 @@ pub struct Server {
     pub config: ServerConfig,
     /// Order the server is displayed in the UI
     pub order: u32,
+    /// Whether the server is restricted to read only access
+    pub read_only: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -29,6 +36,15 @@ pub struct CreateServer {
     pub name: String,
     pub config: ServerConfig,
     pub order: u32,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Changes to apply to an existing server, fields left as [None] are unchanged
+#[derive(Deserialize)]
+pub struct UpdateServer {
+    pub name: Option<String>,
+    pub config: Option<ServerConfig>,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -42,11 +58,10 @@ pub enum ServerConfig {
     Config { data: ServerConfigData },
 
     /// Config is stored as an encrypted blob
-    Encrypted {
-        salt: Vec<u8>,
-        nonce: Vec<u8>,
-        data: Vec<u8>,
-    },
+    Encrypted(EncryptedContent),
+
+    /// Config is stored encrypted with the master password vault key
+    Vault { nonce: Vec<u8>, data: Vec<u8> },
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -62,13 +77,111 @@ pub struct AdminDatabaseConfiguration {
 pub struct AdminDatabaseSetupUserConfig {
     #[serde(alias = "user")]
     pub username: String,
-    pub password: String,
+    pub password: SecretString,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct ApiConfig {
     pub url: String,
-    pub api_key: Option<String>,
+    pub api_key: Option<SecretString>,
+    /// Timeout in seconds for connecting to the API
+    pub connect_timeout: Option<u64>,
+    /// Timeout in seconds between reads from the API
+    pub read_timeout: Option<u64>,
+    /// Maximum number of idle connections to keep pooled
+    pub pool_max_idle: Option<usize>,
+    /// User agent to send with API requests
+    pub user_agent: Option<String>,
+    /// Request headers the gateway passes through to the API, uses
+    /// the default set when not specified
+    pub forward_headers: Option<Vec<String>>,
+    /// Capture redacted request and response bodies in the gateway history
+    #[serde(default)]
+    pub capture_bodies: bool,
+    /// Cache GET responses in the gateway, revalidated using ETag and
+    /// Last-Modified headers
+    #[serde(default)]
+    pub response_cache: bool,
+    /// Retry policy for transient gateway request failures
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Additional authentication applied to API requests
+    #[serde(default)]
+    pub auth: Vec<ApiAuth>,
+    /// Certificate trust settings, also applied to database connections
+    #[serde(default)]
+    pub tls: TlsConfig,
+    /// Outbound HTTP proxy for API requests
+    pub proxy: Option<ProxyConfig>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct TlsConfig {
+    /// Additional PEM encoded root certificates to trust
+    #[serde(default)]
+    pub root_certificates: Vec<String>,
+    /// Accept invalid certificates, this disables certificate verification
+    /// entirely and must be explicitly opted into for each server
+    #[serde(default)]
+    pub accept_invalid_certificates: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ProxyConfig {
+    /// URL of the proxy (e.g http://proxy.internal:3128)
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+    /// Comma separated list of hosts that bypass the proxy
+    pub no_proxy: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ApiAuth {
+    /// Bearer token sent in the authorization header
+    Bearer { token: SecretString },
+
+    /// HTTP basic authentication sent in the authorization header
+    Basic {
+        username: String,
+        password: Option<SecretString>,
+    },
+
+    /// Client certificate presented to the server for mutual TLS, the
+    /// key must be a PKCS#8 PEM encoded private key
+    ClientCertificate {
+        certificate_pem: String,
+        key_pem: SecretString,
+    },
+
+    /// Static headers sent with every request
+    Headers { headers: BTreeMap<String, String> },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RetryConfig {
+    /// Maximum number of retries after the first attempt
+    pub max_retries: u32,
+    /// Base delay in milliseconds for the exponential backoff
+    pub base_delay_ms: u64,
+    /// Maximum delay in milliseconds between attempts
+    pub max_delay_ms: u64,
+    /// Allow retrying PUT requests, only safe when the API treats them
+    /// as idempotent
+    #[serde(default)]
+    pub retry_put: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay_ms: 200,
+            max_delay_ms: 5000,
+            retry_put: false,
+        }
+    }
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -88,14 +201,15 @@ impl Server {
 
         sqlx::query(
             r#"
-            INSERT INTO "servers" ("id", "name", "config", "order")
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO "servers" ("id", "name", "config", "order", "read_only")
+            VALUES ($1, $2, $3, $4, $5)
         "#,
         )
         .bind(create.id)
         .bind(create.name.as_str())
         .bind(config_value)
         .bind(create.order)
+        .bind(create.read_only)
         .execute(db)
         .await?;
 
@@ -104,6 +218,7 @@ impl Server {
             name: create.name,
             config: create.config,
             order: create.order,
+            read_only: create.read_only,
         })
     }
 
@@ -122,6 +237,77 @@ impl Server {
             .await
     }
 
+    /// Update the name and/or config of the server with `id`
+    pub async fn update(
+        db: impl DbExecutor<'_>,
+        id: ServerId,
+        update: &UpdateServer,
+    ) -> DbResult<()> {
+        let config_value = update
+            .config
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|error| DbErr::Encode(Box::new(error)))?;
+
+        sqlx::query(
+            r#"
+            UPDATE "servers"
+            SET "name" = COALESCE($1, "name"), "config" = COALESCE($2, "config")
+            WHERE "id" = $3
+        "#,
+        )
+        .bind(update.name.as_deref())
+        .bind(config_value)
+        .bind(id)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Set the display order of servers to match the order of `ids`
+    ///
+    /// `ids` must contain every server exactly once, returns false without
+    /// changing the order otherwise
+    pub async fn reorder(db: &DbPool, ids: &[ServerId]) -> DbResult<bool> {
+        let mut tx = db.begin().await?;
+
+        let existing: Vec<(ServerId,)> = sqlx::query_as(r#"SELECT "id" FROM "servers""#)
+            .fetch_all(&mut *tx)
+            .await?;
+        let existing: HashSet<ServerId> = existing.into_iter().map(|(id,)| id).collect();
+        let requested: HashSet<ServerId> = ids.iter().copied().collect();
+
+        if requested.len() != ids.len() || requested != existing {
+            return Ok(false);
+        }
+
+        for (order, id) in ids.iter().enumerate() {
+            sqlx::query(r#"UPDATE "servers" SET "order" = $1 WHERE "id" = $2"#)
+                .bind(order as u32)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Set whether the server with `id` is read only
+    pub async fn set_read_only(
+        db: impl DbExecutor<'_>,
+        id: ServerId,
+        read_only: bool,
+    ) -> DbResult<()> {
+        sqlx::query(r#"UPDATE "servers" SET "read_only" = $1 WHERE "id" = $2"#)
+            .bind(read_only)
+            .bind(id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
     /// Delete a server by `id`
     pub async fn delete_by_id(db: impl DbExecutor<'_>, id: ServerId) -> DbResult<()> {
         sqlx::query(r#"DELETE FROM "servers" WHERE "id" = $1"#)
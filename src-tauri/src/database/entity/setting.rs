@@ -0,0 +1,49 @@
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::types::Json;
+
+use crate::database::{DbErr, DbExecutor, DbResult};
+
+/// Key value store for app wide settings, values are stored as JSON
+pub struct Setting;
+
+impl Setting {
+    /// Get the value of the setting with `key`
+    pub async fn get<T>(db: impl DbExecutor<'_>, key: &str) -> DbResult<Option<T>>
+    where
+        T: DeserializeOwned + Send + Unpin,
+    {
+        let value: Option<Json<T>> =
+            sqlx::query_scalar(r#"SELECT "value" FROM "settings" WHERE "key" = $1"#)
+                .bind(key)
+                .fetch_optional(db)
+                .await?;
+
+        Ok(value.map(|value| value.0))
+    }
+
+    /// Set the value of the setting with `key`
+    pub async fn set<T: Serialize>(db: impl DbExecutor<'_>, key: &str, value: &T) -> DbResult<()> {
+        let value = serde_json::to_value(value).map_err(|error| DbErr::Encode(Box::new(error)))?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO "settings" ("key", "value") VALUES ($1, $2)
+            ON CONFLICT ("key") DO UPDATE SET "value" = "excluded"."value"
+        "#,
+        )
+        .bind(key)
+        .bind(value)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Remove the setting with `key`
+    pub async fn remove(db: impl DbExecutor<'_>, key: &str) -> DbResult<()> {
+        sqlx::query(r#"DELETE FROM "settings" WHERE "key" = $1"#)
+            .bind(key)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1 @@
+ALTER TABLE "servers" ADD COLUMN "read_only" boolean NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS "settings" (
+	"key"	varchar NOT NULL,
+	"value"	jsonb_text NOT NULL,
+	PRIMARY KEY("key")
+);
\ No newline at end of file
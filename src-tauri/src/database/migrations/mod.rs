@@ -1,29 +1,111 @@
+use std::sync::Arc;
+
 use chrono::{DateTime, Utc};
-use sqlx::prelude::FromRow;
+use sha2::{Digest, Sha256};
+use sqlx::{prelude::FromRow, SqliteConnection};
+use thiserror::Error;
 
-use super::{DbPool, DbResult};
+use super::{DbErr, DbPool, DbResult};
 
 fn migrations() -> Vec<SqlMigration> {
-    vec![SqlMigration::new(
-        "m202509221140_create_servers_table",
-        include_str!("m202509221140_create_servers_table.sql"),
-    )]
+    vec![
+        SqlMigration::new(
+            "m202509221140_create_servers_table",
+            include_str!("m202509221140_create_servers_table.sql"),
+        )
+        .with_down(include_str!("m202509221140_create_servers_table.down.sql")),
+        SqlMigration::new(
+            "m202610181000_add_server_read_only",
+            include_str!("m202610181000_add_server_read_only.sql"),
+        )
+        .with_down(include_str!("m202610181000_add_server_read_only.down.sql")),
+        SqlMigration::new(
+            "m202610181100_create_settings_table",
+            include_str!("m202610181100_create_settings_table.sql"),
+        )
+        .with_down(include_str!("m202610181100_create_settings_table.down.sql")),
+    ]
+}
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error(transparent)]
+    Db(#[from] DbErr),
+
+    #[error("migration {0} was modified after it was applied")]
+    ChecksumMismatch(String),
+
+    #[error("migration {0} is not known locally")]
+    UnknownMigration(String),
+
+    #[error("migration {0} does not have a down script")]
+    Irreversible(String),
+}
+
+impl MigrationError {
+    /// Machine readable error code the frontend can act on
+    pub fn code(&self) -> &'static str {
+        match self {
+            MigrationError::Db(_) => "MIGRATION_FAILED",
+            MigrationError::ChecksumMismatch(_) => "MIGRATION_CHECKSUM_MISMATCH",
+            MigrationError::UnknownMigration(_) => "UNKNOWN_MIGRATION",
+            MigrationError::Irreversible(_) => "MIGRATION_IRREVERSIBLE",
+        }
+    }
+}
+
+/// Outcome of migrating the database at startup, a failure is kept instead
+/// of exiting so the UI can report it and offer to restore a backup
+#[derive(Default)]
+pub struct MigrationStatus {
+    error: Option<Arc<MigrationError>>,
+}
+
+impl MigrationStatus {
+    pub fn failed(error: MigrationError) -> Self {
+        Self {
+            error: Some(Arc::new(error)),
+        }
+    }
+
+    /// Error from migrating the database at startup
+    pub fn error(&self) -> Option<Arc<MigrationError>> {
+        self.error.clone()
+    }
 }
 
 pub(crate) trait Migration: Send + Sync {
     fn name(&self) -> &str;
 
-    async fn up(&self, db: &DbPool) -> DbResult<()>;
+    /// Hash of the migration content, used to detect a migration that was
+    /// modified after it was applied
+    fn checksum(&self) -> String;
+
+    async fn up(&self, db: &mut SqliteConnection) -> DbResult<()>;
+
+    /// Revert the migration, returns false if the migration cannot be reverted
+    async fn down(&self, db: &mut SqliteConnection) -> DbResult<bool>;
 }
 
 pub struct SqlMigration {
     name: &'static str,
     sql: &'static str,
+    down_sql: Option<&'static str>,
 }
 
 impl SqlMigration {
     pub fn new(name: &'static str, sql: &'static str) -> Self {
-        Self { name, sql }
+        Self {
+            name,
+            sql,
+            down_sql: None,
+        }
+    }
+
+    /// Set the SQL used to revert the migration
+    pub fn with_down(mut self, down_sql: &'static str) -> Self {
+        self.down_sql = Some(down_sql);
+        self
     }
 }
 
@@ -32,10 +114,23 @@ impl Migration for SqlMigration {
         self.name
     }
 
-    async fn up(&self, db: &DbPool) -> DbResult<()> {
+    fn checksum(&self) -> String {
+        format!("{:x}", Sha256::digest(self.sql.as_bytes()))
+    }
+
+    async fn up(&self, db: &mut SqliteConnection) -> DbResult<()> {
         sqlx::query(self.sql).execute(db).await?;
         Ok(())
     }
+
+    async fn down(&self, db: &mut SqliteConnection) -> DbResult<bool> {
+        let Some(down_sql) = self.down_sql else {
+            return Ok(false);
+        };
+
+        sqlx::query(down_sql).execute(db).await?;
+        Ok(true)
+    }
 }
 
 #[derive(FromRow)]
@@ -43,66 +138,187 @@ struct AppliedMigration {
     name: String,
     #[allow(unused)]
     applied_at: DateTime<Utc>,
+    /// Checksum of the migration when it was applied, migrations applied
+    /// before checksums were recorded have their checksum stored on the
+    /// next startup
+    checksum: Option<String>,
+}
+
+/// Get the names of the migrations that have not been applied yet. The
+/// database is only read, so this is safe to call before taking a backup
+pub async fn get_pending_migrations(db: &DbPool) -> DbResult<Vec<String>> {
+    let applied: Vec<String> = if has_migrations_table(db).await? {
+        sqlx::query_scalar("SELECT name FROM migrations")
+            .fetch_all(db)
+            .await?
+    } else {
+        Vec::new()
+    };
+
+    Ok(migrations()
+        .iter()
+        .map(|migration| migration.name())
+        .filter(|name| !applied.iter().any(|applied| applied.eq(name)))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Check if [migrate] would change the database schema, either by applying
+/// a migration or by bootstrapping the migrations table. The database is
+/// only read
+pub async fn needs_migration(db: &DbPool) -> DbResult<bool> {
+    if !has_migrations_table(db).await? || !has_checksum_column(db).await? {
+        return Ok(true);
+    }
+
+    Ok(!get_pending_migrations(db).await?.is_empty())
 }
 
-pub async fn migrate(db: &DbPool) -> DbResult<()> {
-    if let Err(cause) = create_migrations_table(db).await {
-        tracing::error!(?cause, "failed to create migrations table");
-        return Err(cause);
+pub async fn migrate(db: &DbPool) -> Result<(), MigrationError> {
+    if let Err(cause) = bootstrap_migrations_table(db).await {
+        tracing::error!(?cause, "failed to bootstrap migrations table");
+        return Err(cause.into());
     }
 
     let migrations = migrations();
-    let mut applied = get_applied_migrations(db).await?;
-    let mut migration_names = Vec::new();
+    let applied = get_applied_migrations(db).await?;
+
+    verify_applied_migrations(db, &migrations, &applied).await?;
 
     for migration in &migrations {
         let name = migration.name();
-        migration_names.push(name.to_string());
 
         // Migration already applied
         if applied.iter().any(|applied| applied.name.eq(name)) {
             continue;
         }
 
-        // Apply migration
-        if let Err(cause) = migration.up(db).await {
+        // Apply the migration and store it as applied, a failure at either
+        // step leaves the database as it was before the migration
+        if let Err(cause) = apply_migration(db, migration).await {
             tracing::warn!(?cause, migration = ?name, "failed to apply migration");
-            return Err(cause);
+            return Err(cause.into());
         }
+    }
 
-        // Store applied migration
-        let applied_at = Utc::now();
-        let migration = match create_applied_migration(db, name.to_string(), applied_at).await {
-            Ok(value) => value,
-            Err(cause) => {
-                tracing::warn!(?cause, migration = ?name, "failed to store applied migration");
-                return Err(cause);
-            }
-        };
+    Ok(())
+}
+
+/// Revert the migration `name` and every migration applied after it, newest
+/// first. Each migration is reverted in its own transaction
+pub async fn rollback(db: &DbPool, name: &str) -> Result<(), MigrationError> {
+    let migrations = migrations();
+    let applied = get_applied_migrations(db).await?;
+
+    let position = migrations
+        .iter()
+        .position(|migration| migration.name() == name)
+        .ok_or_else(|| MigrationError::UnknownMigration(name.to_string()))?;
+
+    for migration in migrations[position..].iter().rev() {
+        let name = migration.name();
+
+        if !applied.iter().any(|applied| applied.name.eq(name)) {
+            continue;
+        }
 
-        applied.push(migration);
+        let mut tx = db.begin().await?;
+
+        if !migration.down(&mut *tx).await? {
+            return Err(MigrationError::Irreversible(name.to_string()));
+        }
+
+        sqlx::query("DELETE FROM migrations WHERE name = ?")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(migration = ?name, "reverted migration");
     }
 
-    // Check if a migration was applied but is not known locally (warning)
+    Ok(())
+}
+
+/// Check the applied migrations against the local migrations, storing the
+/// checksum of migrations applied before checksums were recorded
+async fn verify_applied_migrations(
+    db: &DbPool,
+    migrations: &[SqlMigration],
+    applied: &[AppliedMigration],
+) -> Result<(), MigrationError> {
     for applied in applied {
-        if !migration_names.contains(&applied.name) {
+        // Check if a migration was applied but is not known locally (warning)
+        let Some(migration) = migrations
+            .iter()
+            .find(|migration| migration.name() == applied.name)
+        else {
             tracing::warn!(
                 name = applied.name,
                 "database has migration applied that is not known locally",
             );
+            continue;
+        };
+
+        let checksum = migration.checksum();
+
+        match applied.checksum.as_deref() {
+            Some(applied_checksum) if applied_checksum == checksum => {}
+            Some(_) => {
+                tracing::error!(name = applied.name, "applied migration was modified");
+                return Err(MigrationError::ChecksumMismatch(applied.name.clone()));
+            }
+            None => {
+                sqlx::query("UPDATE migrations SET checksum = ? WHERE name = ?")
+                    .bind(&checksum)
+                    .bind(&applied.name)
+                    .execute(db)
+                    .await?;
+            }
         }
     }
 
     Ok(())
 }
 
-async fn create_migrations_table(db: &DbPool) -> DbResult<()> {
+/// Create the migrations table and bring its schema up to date. The table
+/// records the applied migrations so it cannot be changed by a migration,
+/// changes to it are made here as bootstrap steps that check if they are
+/// needed before running
+async fn bootstrap_migrations_table(db: &DbPool) -> DbResult<()> {
     sqlx::query(include_str!("m202509221140_create_migrations_table.sql"))
         .execute(db)
         .await?;
+
+    // Tables created before checksums were recorded
+    if !has_checksum_column(db).await? {
+        sqlx::query(include_str!("bootstrap_add_migrations_checksum.sql"))
+            .execute(db)
+            .await?;
+    }
+
     Ok(())
 }
 
+async fn has_migrations_table(db: &DbPool) -> DbResult<bool> {
+    let (exists,): (bool,) = sqlx::query_as(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'migrations')",
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(exists)
+}
+
+async fn has_checksum_column(db: &DbPool) -> DbResult<bool> {
+    let (exists,): (bool,) = sqlx::query_as(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info('migrations') WHERE name = 'checksum')",
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(exists)
+}
+
 async fn get_applied_migrations(db: &DbPool) -> DbResult<Vec<AppliedMigration>> {
     let result: Vec<AppliedMigration> = sqlx::query_as("SELECT * FROM migrations")
         .fetch_all(db)
@@ -110,17 +326,19 @@ async fn get_applied_migrations(db: &DbPool) -> DbResult<Vec<AppliedMigration>>
     Ok(result)
 }
 
-async fn create_applied_migration(
-    db: &DbPool,
-    name: String,
-    applied_at: DateTime<Utc>,
-) -> DbResult<AppliedMigration> {
-    sqlx::query("INSERT INTO migrations (name, applied_at) VALUES (?, ?)")
-        .bind(&name)
-        .bind(applied_at)
-        .execute(db)
+/// Apply a migration and store it as applied within a single transaction
+async fn apply_migration(db: &DbPool, migration: &impl Migration) -> DbResult<()> {
+    let mut tx = db.begin().await?;
+
+    migration.up(&mut *tx).await?;
+
+    sqlx::query("INSERT INTO migrations (name, applied_at, checksum) VALUES (?, ?, ?)")
+        .bind(migration.name())
+        .bind(Utc::now())
+        .bind(migration.checksum())
+        .execute(&mut *tx)
         .await?;
 
-    let model = AppliedMigration { name, applied_at };
-    Ok(model)
+    tx.commit().await?;
+    Ok(())
 }
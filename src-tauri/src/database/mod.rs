@@ -6,9 +6,13 @@ use tokio::fs::{create_dir_all, File};
 pub use sqlx;
 pub use sqlx::SqliteExecutor as DbExecutor;
 
+pub mod backup;
 pub mod entity;
 pub mod migrations;
 
+/// File name of the app database within the app data folder
+pub const DATABASE_FILE_NAME: &str = "app.db";
+
 pub type DbPool = SqlitePool;
 pub type DbErr = sqlx::Error;
 pub type DbResult<T> = Result<T, DbErr>;
@@ -24,12 +28,18 @@ pub enum DbConnectError {
     CreatePath(std::io::Error),
     #[error("failed to create database file: {0}")]
     CreateFile(std::io::Error),
+    #[error("failed to back up database: {0}")]
+    Backup(DbErr),
 }
 
 /// Connects to the SQLite database at the provided path, creating a
-/// new database file if none exist
+/// new database file if none exist. Existing databases with pending
+/// migrations are backed up, the migrations are applied separately by
+/// [migrations::migrate]
 pub async fn connect_database(path: PathBuf) -> Result<DbPool, DbConnectError> {
-    if !path.exists() {
+    let is_new = !path.exists();
+
+    if is_new {
         let parent = path.parent().ok_or(DbConnectError::InvalidPath)?;
 
         create_dir_all(parent)
@@ -41,15 +51,24 @@ pub async fn connect_database(path: PathBuf) -> Result<DbPool, DbConnectError> {
             .map_err(DbConnectError::CreateFile)?;
     }
 
+    let backups_dir = backup::backups_dir(&path);
     let path = path.to_str().ok_or(DbConnectError::InvalidPath)?;
     let path = format!("sqlite://{path}");
 
     let options = SqliteConnectOptions::from_str(&path)?;
     let db = SqlitePool::connect_with(options).await?;
 
-    if let Err(cause) = migrations::migrate(&db).await {
-        tracing::error!(?cause, "failed to run database migrations");
-        return Err(cause.into());
+    // Snapshot existing databases before they are migrated, the check only
+    // reads the database so the backup is taken before any schema change
+    if !is_new && migrations::needs_migration(&db).await? {
+        let backup = backup::create_backup(&db, &backups_dir)
+            .await
+            .map_err(DbConnectError::Backup)?;
+        tracing::info!(?backup, "backed up database before migrating");
+
+        if let Err(cause) = backup::prune_backups(&backups_dir).await {
+            tracing::warn!(?cause, "failed to remove old database backups");
+        }
     }
 
     Ok(db)
@@ -0,0 +1,138 @@
+//! ETag aware cache for GET responses passing through the gateway
+//!
+//! Cached responses are always revalidated with the docbox API using the
+//! stored ETag / Last-Modified values, a 304 response is answered from the
+//! cache. Any mutating request for a tenant invalidates every cached
+//! response for that tenant.
+
+use std::{sync::Arc, time::Duration};
+
+use moka::future::Cache;
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    StatusCode,
+};
+
+use crate::database::entity::server::ServerId;
+
+/// Total size of the response bodies that can be cached
+const MAX_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Largest single response body that will be cached
+const MAX_CACHED_BODY: usize = 1024 * 1024;
+
+/// Time an unused entry will remain in the cache
+const CACHE_TIME_TO_IDLE: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub struct CacheKey {
+    pub server_id: ServerId,
+    pub tenant_id: String,
+    pub env: String,
+    /// Docbox path including the query string
+    pub path: String,
+}
+
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl CachedResponse {
+    /// Create a cached response if the response is allowed to be cached
+    /// and can be revalidated
+    pub fn new(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Option<CachedResponse> {
+        if status != StatusCode::OK || body.len() > MAX_CACHED_BODY {
+            return None;
+        }
+
+        let no_store = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("no-store"));
+
+        if no_store
+            || !(headers.contains_key(header::ETAG) || headers.contains_key(header::LAST_MODIFIED))
+        {
+            return None;
+        }
+
+        Some(CachedResponse {
+            status,
+            headers: headers.clone(),
+            body: body.to_vec(),
+        })
+    }
+
+    /// Conditional headers for revalidating the cached response
+    pub fn conditional_headers(&self) -> Vec<(header::HeaderName, HeaderValue)> {
+        let mut headers = Vec::new();
+
+        if let Some(etag) = self.headers.get(header::ETAG) {
+            headers.push((header::IF_NONE_MATCH, etag.clone()));
+        }
+
+        if let Some(last_modified) = self.headers.get(header::LAST_MODIFIED) {
+            headers.push((header::IF_MODIFIED_SINCE, last_modified.clone()));
+        }
+
+        headers
+    }
+}
+
+pub struct ResponseCache {
+    cache: Cache<CacheKey, Arc<CachedResponse>>,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        let cache = Cache::builder()
+            .max_capacity(MAX_CACHE_SIZE)
+            .weigher(|_key, value: &Arc<CachedResponse>| {
+                u32::try_from(value.body.len()).unwrap_or(u32::MAX)
+            })
+            .time_to_idle(CACHE_TIME_TO_IDLE)
+            .support_invalidation_closures()
+            .build();
+
+        Self { cache }
+    }
+}
+
+impl ResponseCache {
+    pub async fn get(&self, key: &CacheKey) -> Option<Arc<CachedResponse>> {
+        self.cache.get(key).await
+    }
+
+    pub async fn insert(&self, key: CacheKey, response: CachedResponse) {
+        self.cache.insert(key, Arc::new(response)).await;
+    }
+
+    pub async fn remove(&self, key: &CacheKey) {
+        self.cache.invalidate(key).await;
+    }
+
+    /// Invalidate all cached responses for a server
+    pub fn invalidate_server(&self, server_id: ServerId) {
+        if let Err(error) = self
+            .cache
+            .invalidate_entries_if(move |key, _| key.server_id == server_id)
+        {
+            tracing::error!(?error, "failed to invalidate server cache");
+        }
+    }
+
+    /// Invalidate all cached responses for a tenant
+    pub fn invalidate_tenant(&self, server_id: ServerId, tenant_id: &str, env: &str) {
+        let tenant_id = tenant_id.to_string();
+        let env = env.to_string();
+
+        if let Err(error) = self.cache.invalidate_entries_if(move |key, _| {
+            key.server_id == server_id && key.tenant_id == tenant_id && key.env == env
+        }) {
+            tracing::error!(?error, "failed to invalidate tenant cache");
+        }
+    }
+}
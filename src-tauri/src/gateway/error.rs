@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use reqwest::StatusCode;
+use serde::Serialize;
+use tauri::http::{self, HeaderValue, Response};
+use thiserror::Error;
+
+use crate::database::DbErr;
+
+use super::with_cors_headers;
+
+/// Errors that can occur while handling a gateway request
+#[derive(Debug, Error)]
+pub enum GatewayError {
+    #[error("request path is missing the server ID")]
+    MissingServerId,
+
+    #[error("request path server ID is not a valid UUID")]
+    InvalidServerId,
+
+    #[error("request path is missing the tenant ID")]
+    MissingTenantId,
+
+    #[error("request path is missing the tenant environment")]
+    MissingTenantEnv,
+
+    #[error("request path tenant ID is not a valid UUID")]
+    InvalidTenantId,
+
+    #[error("request path tenant environment is not a known environment")]
+    UnknownTenantEnv,
+
+    #[error("request origin is not allowed")]
+    OriginNotAllowed,
+
+    #[error("tenant not found")]
+    TenantNotFound,
+
+    #[error("failed to lookup tenants: {0}")]
+    TenantLookup(Arc<eyre::Report>),
+
+    #[error("server not found")]
+    ServerNotFound,
+
+    #[error("server is not loaded")]
+    ServerNotLoaded,
+
+    #[error("server is read only")]
+    ReadOnly,
+
+    #[error("failed to lookup server: {0}")]
+    Database(DbErr),
+
+    #[error("failed to request docbox: {0}")]
+    UpstreamRequest(reqwest::Error),
+
+    #[error("failed to read docbox response body: {0}")]
+    UpstreamBody(reqwest::Error),
+
+    #[error("request was cancelled")]
+    Cancelled,
+
+    #[error("a request with the same ID is already in flight")]
+    DuplicateRequestId,
+
+    #[error("no recorded response matches the request")]
+    ReplayNotFound,
+
+    #[error("recorded response is invalid")]
+    InvalidReplay,
+
+    #[error("recorded response body was streamed and not captured")]
+    ReplayNotCaptured,
+
+    #[error("failed to create response: {0}")]
+    Response(http::Error),
+}
+
+/// JSON body sent to the webview when a gateway request fails
+#[derive(Serialize)]
+struct GatewayErrorBody {
+    message: String,
+    code: &'static str,
+}
+
+impl GatewayError {
+    /// HTTP status code the error is reported with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            GatewayError::MissingServerId
+            | GatewayError::InvalidServerId
+            | GatewayError::MissingTenantId
+            | GatewayError::MissingTenantEnv
+            | GatewayError::InvalidTenantId
+            | GatewayError::UnknownTenantEnv => StatusCode::BAD_REQUEST,
+            GatewayError::ServerNotFound
+            | GatewayError::TenantNotFound
+            | GatewayError::ReplayNotFound
+            | GatewayError::ReplayNotCaptured => StatusCode::NOT_FOUND,
+            GatewayError::ServerNotLoaded | GatewayError::DuplicateRequestId => {
+                StatusCode::CONFLICT
+            }
+            GatewayError::ReadOnly | GatewayError::OriginNotAllowed => StatusCode::FORBIDDEN,
+            // Non standard "Client Closed Request" status
+            GatewayError::Cancelled => {
+                StatusCode::from_u16(499).unwrap_or(StatusCode::REQUEST_TIMEOUT)
+            }
+            GatewayError::UpstreamRequest(_)
+            | GatewayError::UpstreamBody(_)
+            | GatewayError::TenantLookup(_) => StatusCode::BAD_GATEWAY,
+            GatewayError::Database(_) | GatewayError::InvalidReplay | GatewayError::Response(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Machine readable error code the frontend can act on
+    pub fn code(&self) -> &'static str {
+        match self {
+            GatewayError::MissingServerId
+            | GatewayError::InvalidServerId
+            | GatewayError::MissingTenantId
+            | GatewayError::MissingTenantEnv
+            | GatewayError::InvalidTenantId
+            | GatewayError::UnknownTenantEnv => "INVALID_PATH",
+            GatewayError::ServerNotFound => "SERVER_NOT_FOUND",
+            GatewayError::TenantNotFound => "TENANT_NOT_FOUND",
+            GatewayError::TenantLookup(_) => "TENANT_LOOKUP",
+            GatewayError::OriginNotAllowed => "ORIGIN_NOT_ALLOWED",
+            GatewayError::ServerNotLoaded => "SERVER_NOT_LOADED",
+            GatewayError::ReadOnly => "READ_ONLY",
+            GatewayError::Cancelled => "CANCELLED",
+            GatewayError::DuplicateRequestId => "DUPLICATE_REQUEST_ID",
+            GatewayError::UpstreamRequest(_) => "UPSTREAM_REQUEST",
+            GatewayError::UpstreamBody(_) => "UPSTREAM_BODY",
+            GatewayError::ReplayNotFound => "REPLAY_NOT_FOUND",
+            GatewayError::InvalidReplay => "INVALID_REPLAY",
+            GatewayError::ReplayNotCaptured => "REPLAY_NOT_CAPTURED",
+            GatewayError::Database(_) | GatewayError::Response(_) => "INTERNAL",
+        }
+    }
+
+    /// Convert the error into a JSON response for the webview
+    pub fn into_response(self) -> http::Response<Vec<u8>> {
+        let body = GatewayErrorBody {
+            message: self.to_string(),
+            code: self.code(),
+        };
+
+        // Serializing a struct of strings cannot fail
+        let body = serde_json::to_vec(&body).unwrap_or_default();
+
+        with_cors_headers(Response::builder())
+            .status(self.status())
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )
+            .body(body)
+            .unwrap_or_else(|_| {
+                let mut response = Response::new(Vec::new());
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                response
+            })
+    }
+}
@@ -0,0 +1,416 @@
+//! HTTP Archive (HAR) recording and replay of gateway traffic
+//!
+//! Recording captures the exchanges for a server so they can be exported
+//! to a HAR file. Replaying a HAR makes the gateway answer requests for a
+//! server from the recorded responses instead of the docbox API, this does
+//! not require the server to be loaded.
+
+use std::collections::HashMap;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use tauri::http::{self, Response};
+use tokio::sync::Mutex;
+
+use crate::database::entity::server::ServerId;
+
+use super::{with_cors_headers, GatewayError};
+
+/// HAR format version produced by the recorder
+const HAR_VERSION: &str = "1.2";
+
+/// Headers identifying the tenant a request was made for
+const TENANT_ID_HEADER: &str = "x-tenant-id";
+const TENANT_ENV_HEADER: &str = "x-tenant-env";
+
+/// Comment on the content of responses whose body was streamed, replaying
+/// these fails rather than serving an empty body
+const NOT_CAPTURED_COMMENT: &str = "body was streamed and not captured";
+
+#[derive(Serialize, Deserialize)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HarLog {
+    pub version: String,
+    pub creator: HarCreator,
+    pub entries: Vec<HarEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HarCreator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    pub started_date_time: DateTime<Utc>,
+    /// Total time taken by the request in milliseconds
+    pub time: f64,
+    pub request: HarRequest,
+    pub response: HarResponse,
+    #[serde(default)]
+    pub cache: serde_json::Value,
+    pub timings: HarTimings,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    pub headers: Vec<HarHeader>,
+    #[serde(default)]
+    pub query_string: Vec<HarHeader>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarContent>,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub http_version: String,
+    pub headers: Vec<HarHeader>,
+    pub content: HarContent,
+    #[serde(default)]
+    pub redirect_url: String,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+/// Name value pair used for both headers and query parameters
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HarHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// Body content, text bodies are stored as is while binary bodies are
+/// stored as base64
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    pub size: i64,
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HarTimings {
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+impl Har {
+    pub fn new(entries: Vec<HarEntry>) -> Self {
+        Har {
+            log: HarLog {
+                version: HAR_VERSION.to_string(),
+                creator: HarCreator {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+                entries,
+            },
+        }
+    }
+}
+
+impl HarEntry {
+    /// Create an entry from a forwarded exchange, `response_body` is [None]
+    /// when the body was streamed and never held in memory
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        started_at: DateTime<Utc>,
+        time_ms: f64,
+        method: &str,
+        url: &str,
+        request_headers: &HeaderMap,
+        request_body: &[u8],
+        status: StatusCode,
+        response_headers: &HeaderMap,
+        response_body: Option<&[u8]>,
+    ) -> Self {
+        let query_string = reqwest::Url::parse(url)
+            .map(|url| {
+                url.query_pairs()
+                    .map(|(name, value)| HarHeader {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let post_data =
+            (!request_body.is_empty()).then(|| HarContent::new(request_headers, request_body));
+
+        let content = match response_body {
+            Some(body) => HarContent::new(response_headers, body),
+            None => HarContent {
+                size: -1,
+                mime_type: content_type(response_headers),
+                text: None,
+                encoding: None,
+                comment: Some(NOT_CAPTURED_COMMENT.to_string()),
+            },
+        };
+
+        HarEntry {
+            started_date_time: started_at,
+            time: time_ms,
+            request: HarRequest {
+                method: method.to_string(),
+                url: url.to_string(),
+                http_version: "HTTP/1.1".to_string(),
+                headers: har_headers(request_headers),
+                query_string,
+                post_data,
+                headers_size: -1,
+                body_size: request_body.len() as i64,
+            },
+            response: HarResponse {
+                status: status.as_u16(),
+                status_text: status.canonical_reason().unwrap_or_default().to_string(),
+                http_version: "HTTP/1.1".to_string(),
+                headers: har_headers(response_headers),
+                body_size: content.size,
+                content,
+                redirect_url: String::new(),
+                headers_size: -1,
+            },
+            cache: serde_json::Value::Object(Default::default()),
+            timings: HarTimings {
+                send: 0.0,
+                wait: time_ms,
+                receive: 0.0,
+            },
+        }
+    }
+
+    fn request_header(&self, name: &str) -> Option<&str> {
+        self.request
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+
+    /// Check if the entry was recorded for the provided request
+    fn matches(&self, method: &str, tenant_id: &str, env: &str, path: &str) -> bool {
+        if !self.request.method.eq_ignore_ascii_case(method)
+            || self.request_header(TENANT_ID_HEADER) != Some(tenant_id)
+            || self.request_header(TENANT_ENV_HEADER) != Some(env)
+        {
+            return false;
+        }
+
+        // Compare the docbox path ignoring the server API URL
+        let Ok(url) = reqwest::Url::parse(&self.request.url) else {
+            return false;
+        };
+
+        let recorded = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        recorded.ends_with(&format!("/{path}"))
+    }
+}
+
+impl HarContent {
+    fn new(headers: &HeaderMap, body: &[u8]) -> Self {
+        let (text, encoding) = match std::str::from_utf8(body) {
+            Ok(text) => (text.to_string(), None),
+            Err(_) => (BASE64_STANDARD.encode(body), Some("base64".to_string())),
+        };
+
+        HarContent {
+            size: body.len() as i64,
+            mime_type: content_type(headers),
+            text: Some(text),
+            encoding,
+            comment: None,
+        }
+    }
+
+    /// Check if the body was recorded, streamed bodies are not held in
+    /// memory so only their headers are recorded
+    fn is_captured(&self) -> bool {
+        self.text.is_some() || self.size == 0
+    }
+
+    fn decode(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        let text = self.text.as_deref().unwrap_or_default();
+        match self.encoding.as_deref() {
+            Some("base64") => BASE64_STANDARD.decode(text),
+            _ => Ok(text.as_bytes().to_vec()),
+        }
+    }
+}
+
+impl HarResponse {
+    /// Create a gateway response from the recorded response
+    pub fn to_response(&self) -> Result<http::Response<Vec<u8>>, GatewayError> {
+        if !self.content.is_captured() {
+            return Err(GatewayError::ReplayNotCaptured);
+        }
+
+        let body = self
+            .content
+            .decode()
+            .map_err(|_| GatewayError::InvalidReplay)?;
+        let status = StatusCode::from_u16(self.status).map_err(|_| GatewayError::InvalidReplay)?;
+
+        let mut response_builder = with_cors_headers(Response::builder()).status(status);
+
+        for header in &self.headers {
+            // Recorded body length may differ from the original transfer
+            if header.name.eq_ignore_ascii_case("content-length")
+                || header.name.eq_ignore_ascii_case("transfer-encoding")
+            {
+                continue;
+            }
+
+            response_builder = response_builder.header(&header.name, &header.value);
+        }
+
+        response_builder.body(body).map_err(GatewayError::Response)
+    }
+}
+
+/// Replay of a loaded HAR file
+struct HarReplay {
+    entries: Vec<HarEntry>,
+    /// Number of times each entry has been served
+    served: Vec<usize>,
+}
+
+/// Active HAR recordings and replays for each server
+#[derive(Default)]
+pub struct HarSessions {
+    recordings: Mutex<HashMap<ServerId, Vec<HarEntry>>>,
+    replays: Mutex<HashMap<ServerId, HarReplay>>,
+}
+
+impl HarSessions {
+    /// Start recording exchanges for a server, discarding any existing recording
+    pub async fn start_recording(&self, server_id: ServerId) {
+        self.recordings.lock().await.insert(server_id, Vec::new());
+    }
+
+    pub async fn is_recording(&self, server_id: ServerId) -> bool {
+        self.recordings.lock().await.contains_key(&server_id)
+    }
+
+    /// Add an entry to the recording for a server if one is active
+    pub async fn record(&self, server_id: ServerId, entry: HarEntry) {
+        if let Some(entries) = self.recordings.lock().await.get_mut(&server_id) {
+            entries.push(entry);
+        }
+    }
+
+    /// Stop recording for a server and return the recorded archive
+    pub async fn stop_recording(&self, server_id: ServerId) -> Option<Har> {
+        self.recordings
+            .lock()
+            .await
+            .remove(&server_id)
+            .map(Har::new)
+    }
+
+    /// Answer requests for a server from the provided archive
+    pub async fn start_replay(&self, server_id: ServerId, har: Har) {
+        let entries = har.log.entries;
+        let served = vec![0; entries.len()];
+        self.replays
+            .lock()
+            .await
+            .insert(server_id, HarReplay { entries, served });
+    }
+
+    /// Stop replaying for a server, returns whether a replay was active
+    pub async fn stop_replay(&self, server_id: ServerId) -> bool {
+        self.replays.lock().await.remove(&server_id).is_some()
+    }
+
+    /// Discard any recording or replay for a server
+    pub async fn clear_server(&self, server_id: ServerId) {
+        self.recordings.lock().await.remove(&server_id);
+        self.replays.lock().await.remove(&server_id);
+    }
+
+    /// Find the recorded entry for a request
+    ///
+    /// Returns [None] when the server is not replaying, otherwise the matching
+    /// entry if one exists. Repeated requests are answered by successive
+    /// recordings of the same request, repeating the last once exhausted.
+    pub async fn find_replay(
+        &self,
+        server_id: ServerId,
+        method: &str,
+        tenant_id: &str,
+        env: &str,
+        path: &str,
+    ) -> Option<Option<HarEntry>> {
+        let replays = &mut *self.replays.lock().await;
+        let replay = replays.get_mut(&server_id)?;
+
+        let matches: Vec<usize> = replay
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.matches(method, tenant_id, env, path))
+            .map(|(index, _)| index)
+            .collect();
+
+        let index = matches
+            .iter()
+            .copied()
+            .find(|index| replay.served[*index] == 0)
+            .or_else(|| matches.last().copied());
+
+        Some(index.map(|index| {
+            replay.served[index] += 1;
+            replay.entries[index].clone()
+        }))
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> String {
+    headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn har_headers(headers: &HeaderMap) -> Vec<HarHeader> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            Some(HarHeader {
+                name: name.to_string(),
+                value: value.to_str().ok()?.to_string(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,141 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::database::entity::server::ServerId;
+
+/// Number of exchanges kept per server
+pub const HISTORY_CAPACITY: usize = 200;
+
+/// Largest body that will be captured into the history
+const MAX_CAPTURED_BODY: usize = 64 * 1024;
+
+/// Replacement value for redacted body fields
+const REDACTED: &str = "[REDACTED]";
+
+/// Body field names that are never captured
+const REDACTED_FIELDS: &[&str] = &[
+    "password",
+    "secret",
+    "token",
+    "api_key",
+    "apikey",
+    "authorization",
+    "access_key",
+];
+
+/// Single request/response pair that passed through the gateway
+#[derive(Clone, Serialize)]
+pub struct GatewayExchange {
+    pub id: Uuid,
+    pub server_id: ServerId,
+    pub started_at: DateTime<Utc>,
+    pub method: String,
+    /// Rewritten URL the request was sent to
+    pub url: Option<String>,
+    pub tenant_id: String,
+    pub env: String,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub latency_ms: u64,
+    pub request_size: usize,
+    /// Size of the response body, not known for streamed responses
+    pub response_size: Option<usize>,
+    pub streamed: bool,
+    /// Response was answered from the gateway response cache
+    pub cached: bool,
+    /// Number of attempts made, more than one when the request was retried
+    pub attempts: u32,
+    pub request_body: Option<String>,
+    pub response_body: Option<String>,
+}
+
+/// Bounded history of recent gateway exchanges for each server
+pub struct GatewayHistory {
+    capacity: usize,
+    servers: Mutex<HashMap<ServerId, VecDeque<GatewayExchange>>>,
+}
+
+impl Default for GatewayHistory {
+    fn default() -> Self {
+        Self::new(HISTORY_CAPACITY)
+    }
+}
+
+impl GatewayHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            servers: Default::default(),
+        }
+    }
+
+    /// Add an exchange, dropping the oldest one if the history is full
+    pub async fn push(&self, exchange: GatewayExchange) {
+        let servers = &mut *self.servers.lock().await;
+        let history = servers.entry(exchange.server_id).or_default();
+
+        while history.len() >= self.capacity {
+            history.pop_front();
+        }
+
+        history.push_back(exchange);
+    }
+
+    /// Get the exchanges for a server, oldest first
+    pub async fn get(&self, server_id: ServerId) -> Vec<GatewayExchange> {
+        self.servers
+            .lock()
+            .await
+            .get(&server_id)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub async fn clear(&self, server_id: ServerId) {
+        self.servers.lock().await.remove(&server_id);
+    }
+}
+
+/// Capture a body for the history, JSON bodies have sensitive fields
+/// redacted and non text bodies are not captured
+pub fn capture_body(content_type: Option<&str>, body: &[u8]) -> Option<String> {
+    if body.is_empty() || body.len() > MAX_CAPTURED_BODY {
+        return None;
+    }
+
+    let content_type = content_type.unwrap_or_default();
+
+    if content_type.contains("json") {
+        let mut value: Value = serde_json::from_slice(body).ok()?;
+        redact_value(&mut value);
+        return serde_json::to_string(&value).ok();
+    }
+
+    if content_type.starts_with("text/") {
+        return String::from_utf8(body.to_vec()).ok();
+    }
+
+    None
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                let key = key.to_lowercase();
+                if REDACTED_FIELDS.iter().any(|field| key.contains(field)) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
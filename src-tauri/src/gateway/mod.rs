@@ -0,0 +1,710 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+    time::Instant,
+};
+
+use chrono::Utc;
+use futures::future::{AbortHandle, Abortable, Aborted};
+use itertools::Itertools;
+use reqwest::{
+    header::{HeaderMap, HeaderName},
+    Method, StatusCode,
+};
+use tauri::{
+    http::{self, request::Parts, HeaderValue, Response, Uri},
+    AppHandle, Emitter,
+};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        entity::server::{Server, ServerId},
+        DbPool,
+    },
+    server::{lock::IdleLock, ServerStore},
+};
+
+pub mod cache;
+pub mod error;
+pub mod har;
+pub mod history;
+pub mod retry;
+pub mod stream;
+pub mod tenant;
+
+use cache::{CacheKey, CachedResponse, ResponseCache};
+pub use error::GatewayError;
+use har::{HarEntry, HarSessions};
+use history::{capture_body, GatewayExchange, GatewayHistory};
+use stream::{StreamResponse, StreamServer, StreamSource};
+use tenant::TenantCache;
+
+/// Event emitted to the webview for every exchange through the gateway
+pub const EXCHANGE_EVENT: &str = "gateway:exchange";
+
+/// Largest upstream body that will be buffered in memory, anything larger
+/// is streamed to the webview through the [StreamServer]
+const MAX_BUFFERED_BODY: usize = 8 * 1024 * 1024;
+
+/// Request headers passed through to the docbox API when the server
+/// does not configure its own list
+const DEFAULT_FORWARD_HEADERS: &[&str] = &[
+    "accept",
+    "accept-encoding",
+    "content-type",
+    "content-length",
+    "range",
+    "if-range",
+    "if-match",
+    "if-none-match",
+    "if-modified-since",
+    "if-unmodified-since",
+];
+
+/// Request headers that make a request conditional or partial, the
+/// response cache is bypassed when the webview sends any of these
+const CONDITIONAL_HEADERS: &[&str] = &[
+    "range",
+    "if-range",
+    "if-match",
+    "if-none-match",
+    "if-modified-since",
+    "if-unmodified-since",
+];
+
+/// Tenant environments the gateway will forward requests for
+const TENANT_ENVS: &[&str] = &["Development", "Production"];
+
+/// Origins the app webview is served from, the gateway only allows
+/// cross origin requests from these
+const ALLOWED_ORIGINS: &[&str] = &[
+    // macOS and Linux
+    "tauri://localhost",
+    // Windows
+    "http://tauri.localhost",
+    "https://tauri.localhost",
+    // Development server
+    #[cfg(debug_assertions)]
+    "http://localhost:1420",
+];
+
+/// Response headers the webview is allowed to read
+const EXPOSE_HEADERS: &str = "accept-ranges, content-encoding, content-length, content-range, \
+    etag, last-modified, x-docbox-stream-url, x-gateway-request-id";
+
+/// Header identifying a gateway request, the webview may provide its own ID
+/// to be able to cancel the request, the ID is echoed back on the response
+///
+/// Loads made by the webview itself (PDF.js, media elements) cannot set the
+/// header so they cannot be cancelled. The protocol responder does not report
+/// when the webview drops a request, so these run until completion or the
+/// server read timeout. Large bodies are relayed by the [StreamServer] which
+/// stops when the webview closes the connection.
+pub const REQUEST_ID_HEADER: &str = "x-gateway-request-id";
+
+/// Gateway proxying requests from the webview to the docbox API of a
+/// loaded server
+pub struct Gateway {
+    app: AppHandle,
+    db: DbPool,
+    server_store: Arc<ServerStore>,
+    streams: Arc<StreamServer>,
+    history: GatewayHistory,
+    har: HarSessions,
+    cache: ResponseCache,
+    tenants: TenantCache,
+    /// Requests through the gateway count as activity for the idle lock
+    idle_lock: Arc<IdleLock>,
+    /// Handles for aborting requests that are still waiting on the docbox API
+    in_flight: Mutex<HashMap<Uuid, AbortHandle>>,
+}
+
+/// Target of a gateway request parsed from the request path
+///
+/// docbox://xxxxxxxxxxx/xxxxxxxx/xxxx/box/xxxxxxxx/search
+///         |- Server -|-Tenant-|-Env-|--- Docbox Path ---|
+///
+struct GatewayTarget {
+    server_id: ServerId,
+    tenant_id: Uuid,
+    env: String,
+    /// Docbox path including the query string
+    path: String,
+}
+
+impl GatewayTarget {
+    fn from_uri(uri: &Uri) -> Result<GatewayTarget, GatewayError> {
+        let path = uri.path().strip_prefix('/').unwrap_or(uri.path());
+        let mut path_parts = path.split('/');
+
+        // Get the server ID
+        let server_id: ServerId = path_parts
+            .next()
+            .filter(|value| !value.is_empty())
+            .ok_or(GatewayError::MissingServerId)?
+            .parse()
+            .map_err(|_| GatewayError::InvalidServerId)?;
+
+        // Get the tenant ID
+        let tenant_id: Uuid = path_parts
+            .next()
+            .filter(|value| !value.is_empty())
+            .ok_or(GatewayError::MissingTenantId)?
+            .parse()
+            .map_err(|_| GatewayError::InvalidTenantId)?;
+
+        // Get the tenant environment
+        let env = path_parts
+            .next()
+            .filter(|value| !value.is_empty())
+            .ok_or(GatewayError::MissingTenantEnv)?;
+
+        if !TENANT_ENVS.contains(&env) {
+            return Err(GatewayError::UnknownTenantEnv);
+        }
+
+        // Collect all remaining parts into the new path
+        let query = uri.query().map(|q| format!("?{q}")).unwrap_or_default();
+        let path = format!("{}{}", path_parts.join("/"), query);
+
+        Ok(GatewayTarget {
+            server_id,
+            tenant_id,
+            env: env.to_string(),
+            path,
+        })
+    }
+}
+
+impl Gateway {
+    pub fn new(
+        app: AppHandle,
+        db: DbPool,
+        server_store: Arc<ServerStore>,
+        streams: Arc<StreamServer>,
+        idle_lock: Arc<IdleLock>,
+    ) -> Self {
+        Self {
+            app,
+            db,
+            server_store,
+            streams,
+            history: GatewayHistory::default(),
+            har: HarSessions::default(),
+            cache: ResponseCache::default(),
+            tenants: TenantCache::default(),
+            idle_lock,
+            in_flight: Default::default(),
+        }
+    }
+
+    /// History of recent exchanges through the gateway
+    pub fn history(&self) -> &GatewayHistory {
+        &self.history
+    }
+
+    /// HAR recordings and replays of gateway traffic
+    pub fn har(&self) -> &HarSessions {
+        &self.har
+    }
+
+    /// Drop everything the gateway holds for a server that was unloaded by the
+    /// lock: streams holding its credentials, cached responses and tenants,
+    /// HAR recordings and replays, and the exchange history
+    pub async fn purge_server(&self, server_id: ServerId) {
+        self.streams.cancel_server(server_id).await;
+        self.cache.invalidate_server(server_id);
+        self.tenants.invalidate_server(server_id).await;
+        self.har.clear_server(server_id).await;
+        self.history.clear(server_id).await;
+    }
+
+    /// Cancel an in-flight request or a response that is still being streamed,
+    /// dropping the upstream connection. Returns whether a request was found
+    pub async fn cancel(&self, request_id: Uuid) -> bool {
+        if let Some(handle) = self.in_flight.lock().await.remove(&request_id) {
+            handle.abort();
+            return true;
+        }
+
+        self.streams.cancel(request_id).await
+    }
+
+    /// Handle requests to the docbox protocol
+    pub async fn handle_request(&self, request: http::Request<Vec<u8>>) -> http::Response<Vec<u8>> {
+        let (parts, body) = request.into_parts();
+        let origin = parts.headers.get(reqwest::header::ORIGIN).cloned();
+
+        // Only the app itself may make cross origin requests to the gateway
+        if let Some(origin) = origin.as_ref() {
+            if !is_allowed_origin(origin.to_str().unwrap_or_default()) {
+                tracing::warn!(?origin, "rejected gateway request from unknown origin");
+                return GatewayError::OriginNotAllowed.into_response();
+            }
+        }
+
+        self.idle_lock.touch();
+
+        let mut response = self.handle_app_request(parts, body).await;
+
+        if let Some(origin) = origin {
+            let headers = response.headers_mut();
+            headers.insert(reqwest::header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            headers.append(reqwest::header::VARY, HeaderValue::from_static("origin"));
+        }
+
+        response
+    }
+
+    /// Handle a request from an allowed origin
+    async fn handle_app_request(&self, parts: Parts, body: Vec<u8>) -> http::Response<Vec<u8>> {
+        // Handle CORS options requests
+        if parts.method == Method::OPTIONS {
+            return with_cors_headers(Response::builder())
+                .body(vec![])
+                .unwrap_or_else(|error| GatewayError::Response(error).into_response());
+        }
+
+        let target = match GatewayTarget::from_uri(&parts.uri) {
+            Ok(value) => value,
+            Err(error) => {
+                tracing::warn!(?error, uri = %parts.uri, "invalid gateway request");
+                return error.into_response();
+            }
+        };
+
+        let request_id = header_str(&parts.headers, REQUEST_ID_HEADER)
+            .and_then(|value| value.parse::<Uuid>().ok())
+            .unwrap_or_else(Uuid::new_v4);
+
+        let start = Instant::now();
+        let mut exchange = GatewayExchange {
+            id: request_id,
+            server_id: target.server_id,
+            started_at: Utc::now(),
+            method: parts.method.to_string(),
+            url: None,
+            tenant_id: target.tenant_id.to_string(),
+            env: target.env.clone(),
+            status: None,
+            error: None,
+            latency_ms: 0,
+            request_size: body.len(),
+            response_size: None,
+            streamed: false,
+            cached: false,
+            attempts: 0,
+            request_body: None,
+            response_body: None,
+        };
+
+        // IDs provided by the webview must be unique, otherwise one request
+        // could replace or remove the abort handle of another
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        match self.in_flight.lock().await.entry(request_id) {
+            Entry::Occupied(_) => {
+                tracing::warn!(%request_id, "rejected gateway request with duplicate ID");
+                return GatewayError::DuplicateRequestId.into_response();
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(abort_handle);
+            }
+        }
+
+        let result = Abortable::new(
+            self.forward(&parts, body, &target, &mut exchange),
+            abort_registration,
+        )
+        .await
+        .unwrap_or_else(|Aborted| Err(GatewayError::Cancelled));
+
+        self.in_flight.lock().await.remove(&request_id);
+
+        exchange.latency_ms = start.elapsed().as_millis() as u64;
+
+        let mut response = match result {
+            Ok(response) => response,
+            Err(error) => {
+                tracing::error!(?error, "failed to handle gateway request");
+                exchange.error = Some(error.to_string());
+                error.into_response()
+            }
+        };
+
+        exchange.status.get_or_insert(response.status().as_u16());
+
+        if let Ok(value) = HeaderValue::from_str(&request_id.to_string()) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+
+        self.record(exchange).await;
+
+        response
+    }
+
+    /// Forward a request to the docbox API of the target server
+    async fn forward(
+        &self,
+        parts: &Parts,
+        body: Vec<u8>,
+        target: &GatewayTarget,
+        exchange: &mut GatewayExchange,
+    ) -> Result<http::Response<Vec<u8>>, GatewayError> {
+        // Answer from a recorded archive when replaying
+        if let Some(entry) = self
+            .har
+            .find_replay(
+                target.server_id,
+                parts.method.as_str(),
+                &target.tenant_id.to_string(),
+                &target.env,
+                &target.path,
+            )
+            .await
+        {
+            let entry = entry.ok_or(GatewayError::ReplayNotFound)?;
+            exchange.url = Some(entry.request.url.clone());
+            exchange.status = Some(entry.response.status);
+            exchange.response_size = usize::try_from(entry.response.content.size).ok();
+            return entry.response.to_response();
+        }
+
+        let server = match self.server_store.get_server(target.server_id).await {
+            Some(value) => value,
+            None => {
+                // Distinguish between unknown servers and ones that need loading
+                let exists = Server::find_by_id(&self.db, target.server_id)
+                    .await
+                    .map_err(GatewayError::Database)?
+                    .is_some();
+
+                return Err(if exists {
+                    GatewayError::ServerNotLoaded
+                } else {
+                    GatewayError::ServerNotFound
+                });
+            }
+        };
+
+        // Read only servers only allow requests that cannot modify data
+        if server.is_read_only()
+            && matches!(
+                parts.method,
+                Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+            )
+        {
+            return Err(GatewayError::ReadOnly);
+        }
+
+        // Reject requests for tenants the server does not have
+        if !self
+            .tenants
+            .contains(&server, target.tenant_id, &target.env)
+            .await?
+        {
+            return Err(GatewayError::TenantNotFound);
+        }
+
+        let capture_bodies = server.config.api.capture_bodies;
+        let recording = self.har.is_recording(target.server_id).await;
+
+        // Rebuild the URI without the stripped prefix
+        let new_uri = format!("{}/{}", &server.config.api.url, target.path);
+        exchange.url = Some(new_uri.clone());
+
+        if capture_bodies {
+            exchange.request_body = capture_body(header_str(&parts.headers, "content-type"), &body);
+        }
+
+        // Pass through the allowed request headers
+        let forward_headers = server
+            .config
+            .api
+            .forward_headers
+            .as_deref()
+            .map(|headers| headers.iter().map(String::as_str).collect::<Vec<_>>())
+            .unwrap_or_else(|| DEFAULT_FORWARD_HEADERS.to_vec());
+
+        let mut request_headers = HeaderMap::new();
+
+        for name in forward_headers {
+            let Ok(header_name) = HeaderName::from_bytes(name.as_bytes()) else {
+                continue;
+            };
+
+            for value in parts.headers.get_all(&header_name) {
+                request_headers.append(header_name.clone(), value.clone());
+            }
+        }
+
+        request_headers.insert(
+            HeaderName::from_static("x-tenant-id"),
+            HeaderValue::from_str(&target.tenant_id.to_string())
+                .map_err(|_| GatewayError::InvalidTenantId)?,
+        );
+        request_headers.insert(
+            HeaderName::from_static("x-tenant-env"),
+            HeaderValue::from_str(&target.env).map_err(|_| GatewayError::UnknownTenantEnv)?,
+        );
+
+        // Revalidate cached GET responses when the response cache is enabled
+        let cache_key = (server.config.api.response_cache
+            && parts.method == Method::GET
+            && !CONDITIONAL_HEADERS
+                .iter()
+                .any(|name| request_headers.contains_key(*name)))
+        .then(|| CacheKey {
+            server_id: target.server_id,
+            tenant_id: target.tenant_id.to_string(),
+            env: target.env.clone(),
+            path: target.path.clone(),
+        });
+
+        let cached = match cache_key.as_ref() {
+            Some(key) => self.cache.get(key).await,
+            None => None,
+        };
+
+        if let Some(cached) = cached.as_ref() {
+            for (name, value) in cached.conditional_headers() {
+                request_headers.insert(name, value);
+            }
+        }
+
+        // Keep a copy of the request for the archive
+        let recorded_request = recording.then(|| (request_headers.clone(), body.clone()));
+
+        tracing::debug!(method = %parts.method, uri = %new_uri, "forwarding gateway request");
+
+        // Authentication headers always take precedence over forwarded ones
+        for name in server.api_headers.keys() {
+            request_headers.remove(name);
+        }
+
+        // Build the request with headers and body, called again for each retry
+        let send_request = || {
+            server
+                .api_request(parts.method.clone(), &new_uri)
+                .headers(request_headers.clone())
+                .body(body.clone())
+                .send()
+        };
+
+        let retry_config = &server.config.api.retry;
+        let retryable = retry::is_retryable_method(&parts.method, retry_config);
+
+        let start = Instant::now();
+        let mut attempt: u32 = 0;
+        let result = loop {
+            let result = send_request().await;
+
+            let transient = match &result {
+                Ok(response) => retry::is_retryable_status(response.status())
+                    .then(|| retry::retry_after(response.headers())),
+                Err(error) => retry::is_retryable_error(error).then_some(None),
+            };
+
+            let delay = match transient {
+                Some(retry_after) if retryable && attempt < retry_config.max_retries => {
+                    retry::backoff_delay(retry_config, attempt, retry_after)
+                }
+                _ => None,
+            };
+
+            let Some(delay) = delay else {
+                break result;
+            };
+
+            attempt += 1;
+            tracing::warn!(
+                uri = %new_uri,
+                attempt,
+                delay_ms = delay.as_millis() as u64,
+                "retrying failed gateway request"
+            );
+
+            tokio::time::sleep(delay).await;
+        };
+
+        exchange.attempts = attempt + 1;
+
+        // Mutating requests invalidate the cached responses for the tenant
+        if !matches!(parts.method, Method::GET | Method::HEAD) {
+            self.cache.invalidate_tenant(
+                target.server_id,
+                &target.tenant_id.to_string(),
+                &target.env,
+            );
+        }
+
+        let mut resp = result.map_err(GatewayError::UpstreamRequest)?;
+
+        let mut status = resp.status();
+        let mut headers = resp.headers().clone();
+
+        exchange.status = Some(status.as_u16());
+
+        // Only GET responses can be redirected to the stream server
+        let streamable = parts.method == Method::GET;
+
+        // Read the body until its complete or grows too large to buffer
+        let mut body = Vec::new();
+        let mut streamed = None;
+
+        if streamable
+            && resp
+                .content_length()
+                .is_some_and(|length| length > MAX_BUFFERED_BODY as u64)
+        {
+            streamed = Some(Vec::new());
+        } else {
+            while let Some(chunk) = resp.chunk().await.map_err(GatewayError::UpstreamBody)? {
+                body.extend_from_slice(&chunk);
+
+                if streamable && body.len() > MAX_BUFFERED_BODY {
+                    streamed = Some(std::mem::take(&mut body));
+                    break;
+                }
+            }
+        }
+
+        if streamed.is_none() {
+            match (status, cached) {
+                // Cached response is still valid
+                (StatusCode::NOT_MODIFIED, Some(cached)) => {
+                    exchange.cached = true;
+                    status = cached.status;
+                    headers = cached.headers.clone();
+                    body = cached.body.clone();
+                }
+
+                _ => {
+                    if let Some(key) = cache_key {
+                        match CachedResponse::new(status, &headers, &body) {
+                            Some(response) => self.cache.insert(key, response).await,
+                            None => self.cache.remove(&key).await,
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some((request_headers, request_body)) = recorded_request {
+            let entry = HarEntry::new(
+                exchange.started_at,
+                start.elapsed().as_secs_f64() * 1000.0,
+                parts.method.as_str(),
+                &new_uri,
+                &request_headers,
+                &request_body,
+                status,
+                &headers,
+                streamed.is_none().then_some(body.as_slice()),
+            );
+
+            self.har.record(target.server_id, entry).await;
+        }
+
+        if let Some(prefix) = streamed {
+            exchange.streamed = true;
+
+            // Follow up requests to the stream repeat the request with the
+            // authentication headers
+            let mut source_headers = request_headers.clone();
+            source_headers.extend(server.api_headers.clone());
+            let source = StreamSource::new(server.id, server.http.clone(), new_uri, source_headers);
+
+            return self
+                .stream_response(exchange.id, source, resp, prefix)
+                .await;
+        }
+
+        exchange.response_size = Some(body.len());
+
+        if capture_bodies {
+            exchange.response_body = capture_body(header_str(&headers, "content-type"), &body);
+        }
+
+        let mut response_builder = with_cors_headers(Response::builder()).status(status);
+
+        for (key, value) in headers.iter() {
+            response_builder = response_builder.header(key, value);
+        }
+
+        response_builder.body(body).map_err(GatewayError::Response)
+    }
+
+    /// Hand the remaining upstream body off to the stream server and redirect
+    /// the webview to it
+    async fn stream_response(
+        &self,
+        request_id: Uuid,
+        source: StreamSource,
+        response: reqwest::Response,
+        prefix: Vec<u8>,
+    ) -> Result<http::Response<Vec<u8>>, GatewayError> {
+        let url = self
+            .streams
+            .register(
+                request_id,
+                source,
+                StreamResponse {
+                    status: response.status(),
+                    headers: response.headers().clone(),
+                    prefix,
+                    response,
+                },
+            )
+            .await;
+
+        tracing::debug!(%url, "streaming large gateway response");
+
+        with_cors_headers(Response::builder())
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header(reqwest::header::LOCATION, &url)
+            .header(STREAM_URL_HEADER, &url)
+            .body(vec![])
+            .map_err(GatewayError::Response)
+    }
+
+    /// Store an exchange in the history and notify the webview
+    async fn record(&self, exchange: GatewayExchange) {
+        if let Err(error) = self.app.emit(EXCHANGE_EVENT, &exchange) {
+            tracing::warn!(?error, "failed to emit gateway exchange event");
+        }
+
+        self.history.push(exchange).await;
+    }
+}
+
+/// Header exposing the stream URL for clients that cannot follow the redirect
+const STREAM_URL_HEADER: &str = "x-docbox-stream-url";
+
+fn header_str<'a>(headers: &'a http::HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Check if `origin` is one the app webview is served from
+pub fn is_allowed_origin(origin: &str) -> bool {
+    ALLOWED_ORIGINS.contains(&origin)
+}
+
+/// Add the CORS headers the webview requires to a response, the allowed
+/// origin is added once the response is complete
+fn with_cors_headers(builder: http::response::Builder) -> http::response::Builder {
+    builder
+        .header(
+            reqwest::header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("GET, POST, PUT, PATCH, DELETE, OPTIONS"),
+        )
+        .header(
+            reqwest::header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_static("*"),
+        )
+        .header(
+            reqwest::header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSE_HEADERS),
+        )
+}
@@ -0,0 +1,254 @@
+//! Retry policy for idempotent gateway requests
+
+use std::{error::Error, io::ErrorKind, time::Duration};
+
+use chrono::{DateTime, Utc};
+use rand_core::{OsRng, RngCore};
+use reqwest::{header::HeaderMap, Method, StatusCode};
+
+use crate::database::entity::server::RetryConfig;
+
+/// Longest Retry-After the gateway is willing to wait for, responses asking
+/// for a longer wait are returned to the webview instead
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Check if requests using `method` are safe to retry
+pub fn is_retryable_method(method: &Method, config: &RetryConfig) -> bool {
+    match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS => true,
+        Method::PUT => config.retry_put,
+        _ => false,
+    }
+}
+
+/// Check if the response status indicates a transient failure
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Check if the request error indicates a transient failure such as a
+/// refused or reset connection
+pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout() || is_connection_reset(error)
+}
+
+/// Check if the error was caused by the connection being reset or closed,
+/// such as a pooled connection the server closed while it was idle
+fn is_connection_reset(error: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(error);
+
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<std::io::Error>() {
+            if matches!(
+                error.kind(),
+                ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+            ) {
+                return true;
+            }
+        }
+
+        source = error.source();
+    }
+
+    false
+}
+
+/// Parse the Retry-After header as either delay seconds or an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&Utc) - Utc::now();
+    Some(delay.to_std().unwrap_or_default())
+}
+
+/// Delay before the next attempt using jittered exponential backoff,
+/// returns [None] if the server asked for a longer wait than allowed
+pub fn backoff_delay(
+    config: &RetryConfig,
+    attempt: u32,
+    retry_after: Option<Duration>,
+) -> Option<Duration> {
+    let max_delay = config.max_delay_ms;
+    let ceiling = config
+        .base_delay_ms
+        .saturating_mul(2u64.saturating_pow(attempt))
+        .min(max_delay);
+
+    // Full jitter, a random delay between zero and the backoff ceiling
+    let delay = Duration::from_millis(OsRng.next_u64() % ceiling.saturating_add(1));
+
+    match retry_after {
+        Some(retry_after) if retry_after > MAX_RETRY_AFTER => None,
+        Some(retry_after) => Some(delay.max(retry_after)),
+        None => Some(delay),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use reqwest::{header::HeaderMap, Method, StatusCode};
+
+    use super::{
+        backoff_delay, is_connection_reset, is_retryable_method, is_retryable_status, retry_after,
+        MAX_RETRY_AFTER,
+    };
+    use crate::database::entity::server::RetryConfig;
+
+    fn config(base_delay_ms: u64, max_delay_ms: u64) -> RetryConfig {
+        RetryConfig {
+            max_retries: 3,
+            base_delay_ms,
+            max_delay_ms,
+            retry_put: false,
+        }
+    }
+
+    /// Delays never exceed the exponential ceiling for the attempt
+    #[test]
+    fn test_backoff_delay_within_ceiling() {
+        let config = config(100, 10_000);
+
+        for attempt in 0..5 {
+            let ceiling = Duration::from_millis(100 * 2u64.pow(attempt));
+
+            for _ in 0..100 {
+                let delay = backoff_delay(&config, attempt, None).unwrap();
+                assert!(delay <= ceiling);
+            }
+        }
+    }
+
+    /// Delays are capped by the maximum delay
+    #[test]
+    fn test_backoff_delay_capped_by_max_delay() {
+        let config = config(1_000, 1_500);
+
+        for _ in 0..100 {
+            let delay = backoff_delay(&config, 10, None).unwrap();
+            assert!(delay <= Duration::from_millis(1_500));
+        }
+    }
+
+    /// Large attempts and delays must not overflow
+    #[test]
+    fn test_backoff_delay_does_not_overflow() {
+        let config = config(u64::MAX, u64::MAX);
+
+        assert!(backoff_delay(&config, u32::MAX, None).is_some());
+        assert!(backoff_delay(&config, 64, None).is_some());
+    }
+
+    /// A zero ceiling always gives no delay
+    #[test]
+    fn test_backoff_delay_zero_ceiling() {
+        let config = config(0, 0);
+
+        assert_eq!(backoff_delay(&config, 3, None), Some(Duration::ZERO));
+    }
+
+    /// Retry-After is used as the minimum delay unless it is too long
+    #[test]
+    fn test_backoff_delay_retry_after() {
+        let config = config(10, 100);
+
+        let retry_after = Duration::from_secs(2);
+        assert_eq!(
+            backoff_delay(&config, 0, Some(retry_after)),
+            Some(retry_after)
+        );
+
+        assert_eq!(
+            backoff_delay(&config, 0, Some(MAX_RETRY_AFTER)),
+            Some(MAX_RETRY_AFTER)
+        );
+
+        let too_long = MAX_RETRY_AFTER + Duration::from_secs(1);
+        assert_eq!(backoff_delay(&config, 0, Some(too_long)), None);
+    }
+
+    #[test]
+    fn test_retryable_methods() {
+        let mut config = RetryConfig::default();
+
+        let cases = [
+            (Method::GET, true),
+            (Method::HEAD, true),
+            (Method::OPTIONS, true),
+            (Method::PUT, false),
+            (Method::POST, false),
+            (Method::PATCH, false),
+            (Method::DELETE, false),
+        ];
+
+        for (method, expected) in cases {
+            assert_eq!(is_retryable_method(&method, &config), expected, "{method}");
+        }
+
+        // PUT is only retried when explicitly allowed
+        config.retry_put = true;
+        assert!(is_retryable_method(&Method::PUT, &config));
+        assert!(!is_retryable_method(&Method::POST, &config));
+        assert!(!is_retryable_method(&Method::PATCH, &config));
+        assert!(!is_retryable_method(&Method::DELETE, &config));
+    }
+
+    #[test]
+    fn test_retryable_statuses() {
+        let cases = [
+            (StatusCode::OK, false),
+            (StatusCode::NOT_MODIFIED, false),
+            (StatusCode::BAD_REQUEST, false),
+            (StatusCode::NOT_FOUND, false),
+            (StatusCode::TOO_MANY_REQUESTS, false),
+            (StatusCode::INTERNAL_SERVER_ERROR, false),
+            (StatusCode::NOT_IMPLEMENTED, false),
+            (StatusCode::BAD_GATEWAY, true),
+            (StatusCode::SERVICE_UNAVAILABLE, true),
+            (StatusCode::GATEWAY_TIMEOUT, true),
+        ];
+
+        for (status, expected) in cases {
+            assert_eq!(is_retryable_status(status), expected, "{status}");
+        }
+    }
+
+    #[test]
+    fn test_retry_after_seconds() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(reqwest::header::RETRY_AFTER, "5".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(5)));
+
+        headers.insert(reqwest::header::RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    /// Reset connections are found anywhere in the error chain
+    #[test]
+    fn test_connection_reset() {
+        #[derive(Debug, thiserror::Error)]
+        #[error("request failed")]
+        struct Wrapper(#[source] std::io::Error);
+
+        let reset = Wrapper(std::io::ErrorKind::ConnectionReset.into());
+        assert!(is_connection_reset(&reset));
+
+        let denied = Wrapper(std::io::ErrorKind::PermissionDenied.into());
+        assert!(!is_connection_reset(&denied));
+    }
+}
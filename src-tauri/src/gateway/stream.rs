@@ -0,0 +1,561 @@
+//! Loopback HTTP server used to stream large upstream bodies
+//!
+//! Tauri custom protocol responders only accept a complete body, so any
+//! response that is too large to buffer is handed off to this server instead.
+//! The webview is redirected to a URL on 127.0.0.1 where the upstream body is
+//! relayed chunk by chunk as it arrives. The URL stays usable after the first
+//! request, follow up requests (such as ranged requests made while seeking
+//! media) are repeated against the docbox API with their `Range` header.
+
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::future::{AbortHandle, Abortable, Aborted};
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    StatusCode,
+};
+use tauri::async_runtime::spawn;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+use super::{is_allowed_origin, CONDITIONAL_HEADERS, EXPOSE_HEADERS};
+use crate::database::entity::server::ServerId;
+
+/// Time the response of the original request will wait for the webview to
+/// claim it before the upstream connection is released
+const PENDING_STREAM_TTL: Duration = Duration::from_secs(60);
+
+/// Time a stream URL stays usable after it was last requested
+const STREAM_IDLE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Origin sent by the webview after following the cross origin redirect from
+/// the gateway. The webview cannot reveal its real origin for these requests
+/// so the unguessable stream ID acts as the capability instead
+const REDIRECTED_ORIGIN: &str = "null";
+
+/// Maximum size of a request head accepted by the stream server
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+/// Upstream response relayed to the webview
+pub struct StreamResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Portion of the body that was already read before handing off
+    pub prefix: Vec<u8>,
+    /// Response to continue reading the remaining body from
+    pub response: reqwest::Response,
+}
+
+impl StreamResponse {
+    fn new(response: reqwest::Response) -> StreamResponse {
+        StreamResponse {
+            status: response.status(),
+            headers: response.headers().clone(),
+            prefix: Vec::new(),
+            response,
+        }
+    }
+}
+
+/// Upstream GET request a stream was created from, repeated for follow up
+/// requests to the stream URL
+pub struct StreamSource {
+    /// Server the request is made to, streams are cancelled when the server
+    /// is locked as the request holds its credentials
+    server_id: ServerId,
+    client: reqwest::Client,
+    url: String,
+    /// Request headers without any range or conditional headers, these are
+    /// taken from each follow up request instead
+    headers: HeaderMap,
+    /// Range requested by the original request
+    range: Option<HeaderValue>,
+}
+
+impl StreamSource {
+    pub fn new(
+        server_id: ServerId,
+        client: reqwest::Client,
+        url: String,
+        mut headers: HeaderMap,
+    ) -> StreamSource {
+        let range = headers.get(header::RANGE).cloned();
+
+        for name in CONDITIONAL_HEADERS {
+            headers.remove(*name);
+        }
+        headers.remove(header::CONTENT_LENGTH);
+
+        StreamSource {
+            server_id,
+            client,
+            url,
+            headers,
+            range,
+        }
+    }
+
+    /// Repeat the request with the range headers of a follow up request
+    async fn fetch(
+        &self,
+        range: Option<&str>,
+        if_range: Option<&str>,
+    ) -> reqwest::Result<StreamResponse> {
+        let mut request = self.client.get(&self.url).headers(self.headers.clone());
+
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+
+        if let Some(if_range) = if_range {
+            request = request.header(header::IF_RANGE, if_range);
+        }
+
+        let response = request.send().await?;
+        Ok(StreamResponse::new(response))
+    }
+}
+
+/// Stream registered under a gateway request ID
+struct RegisteredStream {
+    source: Arc<StreamSource>,
+    /// Response of the original request, waiting to be claimed by the webview
+    initial: Option<StreamResponse>,
+    created_at: Instant,
+    last_used: Instant,
+}
+
+impl RegisteredStream {
+    fn is_expired(&self) -> bool {
+        self.last_used.elapsed() >= STREAM_IDLE_TTL
+    }
+
+    /// Drop the original response if the webview did not claim it in time
+    fn release_expired_initial(&mut self) {
+        if self.created_at.elapsed() >= PENDING_STREAM_TTL {
+            self.initial = None;
+        }
+    }
+}
+
+/// Connection relaying a stream to the webview
+struct ActiveStream {
+    /// ID of the stream being relayed
+    id: Uuid,
+    server_id: ServerId,
+    handle: AbortHandle,
+}
+
+pub struct StreamServer {
+    addr: SocketAddr,
+    streams: Mutex<HashMap<Uuid, RegisteredStream>>,
+    /// Connections currently relaying a stream to the webview, keyed by
+    /// connection
+    active: Mutex<HashMap<u64, ActiveStream>>,
+    next_connection: AtomicU64,
+}
+
+impl StreamServer {
+    /// Bind the stream server to a random loopback port and start accepting
+    /// connections in the background
+    pub async fn bind() -> std::io::Result<Arc<StreamServer>> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+
+        let server = Arc::new(StreamServer {
+            addr,
+            streams: Default::default(),
+            active: Default::default(),
+            next_connection: AtomicU64::new(0),
+        });
+
+        spawn(accept_connections(server.clone(), listener));
+        spawn(remove_expired_streams(Arc::downgrade(&server)));
+
+        Ok(server)
+    }
+
+    /// Register a stream to be served under the gateway request `id`, returns
+    /// the URL the webview should request to receive the body
+    pub async fn register(
+        &self,
+        id: Uuid,
+        source: StreamSource,
+        initial: StreamResponse,
+    ) -> String {
+        let now = Instant::now();
+
+        self.streams.lock().await.insert(
+            id,
+            RegisteredStream {
+                source: Arc::new(source),
+                initial: Some(initial),
+                created_at: now,
+                last_used: now,
+            },
+        );
+
+        format!("http://{}/stream/{}", self.addr, id)
+    }
+
+    /// Cancel a stream, dropping any upstream responses and making the
+    /// stream URL unusable. Returns whether a stream was found
+    pub async fn cancel(&self, id: Uuid) -> bool {
+        let mut found = self.streams.lock().await.remove(&id).is_some();
+
+        self.active.lock().await.retain(|_, stream| {
+            if stream.id != id {
+                return true;
+            }
+
+            stream.handle.abort();
+            found = true;
+            false
+        });
+
+        found
+    }
+
+    /// Cancel every stream of a server, used when the server is unloaded so
+    /// its credentials are not kept alive by stream URLs
+    pub async fn cancel_server(&self, server_id: ServerId) {
+        self.streams
+            .lock()
+            .await
+            .retain(|_, stream| stream.source.server_id != server_id);
+
+        self.active.lock().await.retain(|_, stream| {
+            if stream.server_id != server_id {
+                return true;
+            }
+
+            stream.handle.abort();
+            false
+        });
+    }
+
+    /// Get the response for a request to the stream `id`, the original
+    /// response is used when the request asks for the same range as the
+    /// original request, otherwise the request is repeated upstream. The
+    /// response is returned along with the server the stream belongs to
+    async fn open(
+        &self,
+        id: Uuid,
+        range: Option<&str>,
+        if_range: Option<&str>,
+    ) -> Option<(ServerId, reqwest::Result<StreamResponse>)> {
+        let source = {
+            let mut streams = self.streams.lock().await;
+            if streams.get(&id)?.is_expired() {
+                streams.remove(&id);
+                return None;
+            }
+
+            let stream = streams.get_mut(&id)?;
+
+            stream.last_used = Instant::now();
+            stream.release_expired_initial();
+
+            let same_range = stream
+                .source
+                .range
+                .as_ref()
+                .and_then(|value| value.to_str().ok())
+                == range;
+
+            match stream.initial.take() {
+                Some(initial) if same_range && if_range.is_none() => {
+                    return Some((stream.source.server_id, Ok(initial)))
+                }
+                _ => stream.source.clone(),
+            }
+        };
+
+        Some((source.server_id, source.fetch(range, if_range).await))
+    }
+}
+
+/// Periodically drop streams the webview never claimed, releasing their
+/// upstream connections
+async fn remove_expired_streams(server: Weak<StreamServer>) {
+    let mut interval = tokio::time::interval(PENDING_STREAM_TTL);
+
+    loop {
+        interval.tick().await;
+
+        let Some(server) = server.upgrade() else {
+            return;
+        };
+
+        server.streams.lock().await.retain(|_, stream| {
+            stream.release_expired_initial();
+            !stream.is_expired()
+        });
+    }
+}
+
+async fn accept_connections(server: Arc<StreamServer>, listener: TcpListener) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(error) => {
+                tracing::warn!(?error, "failed to accept stream connection");
+                continue;
+            }
+        };
+
+        let server = server.clone();
+        spawn(async move {
+            if let Err(error) = serve_connection(&server, socket).await {
+                tracing::debug!(?error, "stream connection closed with error");
+            }
+        });
+    }
+}
+
+async fn serve_connection(server: &StreamServer, mut socket: TcpStream) -> std::io::Result<()> {
+    let head = match read_request_head(&mut socket).await? {
+        Some(value) => value,
+        None => return write_empty(&mut socket, StatusCode::BAD_REQUEST, None).await,
+    };
+
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+
+    // Only the app itself may read streams, either directly or by following
+    // the redirect from the gateway
+    let origin = request_header(&head, "origin");
+    if origin.is_some_and(|origin| origin != REDIRECTED_ORIGIN && !is_allowed_origin(origin)) {
+        return write_empty(&mut socket, StatusCode::FORBIDDEN, None).await;
+    }
+
+    if method == "OPTIONS" {
+        return write_empty(&mut socket, StatusCode::NO_CONTENT, origin).await;
+    }
+
+    if method != "GET" {
+        return write_empty(&mut socket, StatusCode::METHOD_NOT_ALLOWED, origin).await;
+    }
+
+    let Some(id) = target
+        .strip_prefix("/stream/")
+        .and_then(|id| id.parse::<Uuid>().ok())
+    else {
+        return write_empty(&mut socket, StatusCode::NOT_FOUND, origin).await;
+    };
+
+    let range = request_header(&head, "range");
+    let if_range = request_header(&head, "if-range");
+
+    let (server_id, stream) = match server.open(id, range, if_range).await {
+        Some((server_id, Ok(value))) => (server_id, value),
+        Some((_, Err(error))) => {
+            tracing::error!(?error, %id, "failed to repeat streamed docbox request");
+            return write_empty(&mut socket, StatusCode::BAD_GATEWAY, origin).await;
+        }
+        None => return write_empty(&mut socket, StatusCode::NOT_FOUND, origin).await,
+    };
+
+    // Track the connection so the stream can be cancelled while relaying
+    let connection = server.next_connection.fetch_add(1, Ordering::Relaxed);
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
+    server.active.lock().await.insert(
+        connection,
+        ActiveStream {
+            id,
+            server_id,
+            handle: abort_handle,
+        },
+    );
+
+    let result = Abortable::new(
+        relay_stream(&mut socket, stream, origin),
+        abort_registration,
+    )
+    .await;
+
+    server.active.lock().await.remove(&connection);
+
+    match result {
+        Ok(result) => result,
+        Err(Aborted) => {
+            tracing::debug!(%id, "stream was cancelled");
+            Ok(())
+        }
+    }
+}
+
+/// Relay the upstream response to the webview
+async fn relay_stream(
+    socket: &mut TcpStream,
+    mut stream: StreamResponse,
+    origin: Option<&str>,
+) -> std::io::Result<()> {
+    // Bodies of a known length are relayed as is, otherwise chunked encoding is used
+    let chunked = !stream.headers.contains_key(header::CONTENT_LENGTH);
+
+    let mut response_head = status_line(stream.status);
+    for (name, value) in stream.headers.iter() {
+        if is_hop_by_hop(name) || name.as_str().starts_with("access-control-") {
+            continue;
+        }
+
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+
+        response_head.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    if chunked {
+        response_head.push_str("transfer-encoding: chunked\r\n");
+    }
+
+    response_head.push_str(&cors_headers(origin));
+    response_head.push_str("connection: close\r\n\r\n");
+    socket.write_all(response_head.as_bytes()).await?;
+
+    write_body_chunk(socket, &stream.prefix, chunked).await?;
+    drop(std::mem::take(&mut stream.prefix));
+
+    loop {
+        let chunk = match stream.response.chunk().await {
+            Ok(Some(value)) => value,
+            Ok(None) => break,
+            Err(error) => {
+                // Closing the connection without the final chunk signals the
+                // failure to the webview
+                tracing::error!(?error, "failed to read streamed docbox response");
+                return Ok(());
+            }
+        };
+
+        write_body_chunk(socket, &chunk, chunked).await?;
+    }
+
+    if chunked {
+        socket.write_all(b"0\r\n\r\n").await?;
+    }
+
+    socket.flush().await
+}
+
+/// Reads the request head (request line and headers), returns [None] if the
+/// head is too large or the connection is closed early
+async fn read_request_head(socket: &mut TcpStream) -> std::io::Result<Option<String>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+
+    loop {
+        let count = socket.read(&mut chunk).await?;
+        if count == 0 {
+            return Ok(None);
+        }
+
+        buffer.extend_from_slice(&chunk[..count]);
+
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            buffer.truncate(end);
+            return Ok(String::from_utf8(buffer).ok());
+        }
+
+        if buffer.len() > MAX_REQUEST_HEAD {
+            return Ok(None);
+        }
+    }
+}
+
+async fn write_body_chunk(
+    socket: &mut TcpStream,
+    chunk: &[u8],
+    chunked: bool,
+) -> std::io::Result<()> {
+    if chunk.is_empty() {
+        return Ok(());
+    }
+
+    if chunked {
+        socket
+            .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+            .await?;
+        socket.write_all(chunk).await?;
+        socket.write_all(b"\r\n").await
+    } else {
+        socket.write_all(chunk).await
+    }
+}
+
+async fn write_empty(
+    socket: &mut TcpStream,
+    status: StatusCode,
+    origin: Option<&str>,
+) -> std::io::Result<()> {
+    let mut response = status_line(status);
+    response.push_str(&cors_headers(origin));
+    response.push_str("content-length: 0\r\nconnection: close\r\n\r\n");
+    socket.write_all(response.as_bytes()).await?;
+    socket.flush().await
+}
+
+fn status_line(status: StatusCode) -> String {
+    format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )
+}
+
+/// CORS headers for a response, `origin` must already be allowed. Requests
+/// that followed the redirect are answered with the `null` origin they sent
+fn cors_headers(origin: Option<&str>) -> String {
+    let mut headers = format!(
+        "access-control-allow-methods: GET, OPTIONS\r\n\
+        access-control-allow-headers: *\r\n\
+        access-control-expose-headers: {EXPOSE_HEADERS}\r\n"
+    );
+
+    if let Some(origin) = origin {
+        headers.push_str(&format!(
+            "access-control-allow-origin: {origin}\r\nvary: origin\r\n"
+        ));
+    }
+
+    headers
+}
+
+/// Find the value of a header in the request head
+fn request_header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+/// Headers that only apply to the upstream connection and must not be relayed
+fn is_hop_by_hop(name: &header::HeaderName) -> bool {
+    matches!(
+        name.as_str(),
+        "connection"
+            | "keep-alive"
+            | "proxy-connection"
+            | "transfer-encoding"
+            | "te"
+            | "trailer"
+            | "upgrade"
+    )
+}
@@ -0,0 +1,91 @@
+//! Short lived cache of the tenants on each loaded server, used to reject
+//! gateway requests for unknown tenants before contacting the docbox API
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use moka::future::Cache;
+use uuid::Uuid;
+
+use crate::{database::entity::server::ServerId, server::ActiveServer};
+
+use super::GatewayError;
+
+/// Time the tenants of a server are cached for
+const TENANT_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Minimum time between refreshes of a server's tenants caused by lookups
+/// of unknown tenants, stops requests for unknown tenants from querying the
+/// server on every request
+const TENANT_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tenant ID and environment pairs present on a server
+type TenantSet = HashSet<(Uuid, String)>;
+
+pub struct TenantCache {
+    cache: Cache<ServerId, Arc<TenantSet>>,
+    /// Servers refreshed for a missing tenant within the refresh interval
+    refreshed: Cache<ServerId, ()>,
+}
+
+impl Default for TenantCache {
+    fn default() -> Self {
+        let cache = Cache::builder().time_to_live(TENANT_CACHE_TTL).build();
+        let refreshed = Cache::builder()
+            .time_to_live(TENANT_REFRESH_INTERVAL)
+            .build();
+        Self { cache, refreshed }
+    }
+}
+
+impl TenantCache {
+    /// Check if the server has a tenant with the provided ID and environment
+    ///
+    /// Tenants missing from the cached set trigger a single refresh so
+    /// tenants created since the last lookup are found, at most once per
+    /// [TENANT_REFRESH_INTERVAL] for each server
+    pub async fn contains(
+        &self,
+        server: &ActiveServer,
+        tenant_id: Uuid,
+        env: &str,
+    ) -> Result<bool, GatewayError> {
+        let key = (tenant_id, env.to_string());
+
+        if self.get(server).await?.contains(&key) {
+            return Ok(true);
+        }
+
+        if self.refreshed.contains_key(&server.id) {
+            return Ok(false);
+        }
+
+        self.refreshed.insert(server.id, ()).await;
+        self.cache.invalidate(&server.id).await;
+        Ok(self.get(server).await?.contains(&key))
+    }
+
+    /// Forget the cached tenants of a server
+    pub async fn invalidate_server(&self, server_id: ServerId) {
+        self.cache.invalidate(&server_id).await;
+        self.refreshed.invalidate(&server_id).await;
+    }
+
+    async fn get(&self, server: &ActiveServer) -> Result<Arc<TenantSet>, GatewayError> {
+        self.cache
+            .try_get_with(server.id, async {
+                let tenants =
+                    docbox_management::tenant::get_tenants::get_tenants(&server.db_provider)
+                        .await
+                        .map_err(eyre::Report::from)?;
+
+                let tenants: TenantSet = tenants
+                    .into_iter()
+                    .map(|tenant| (tenant.id, tenant.env))
+                    .collect();
+
+                Ok::<_, eyre::Report>(Arc::new(tenants))
+            })
+            .await
+            .map_err(GatewayError::TenantLookup)
+    }
+}
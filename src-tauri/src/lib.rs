@@ -1,4 +1,4 @@
-use std::{error::Error, sync::Arc};
+use std::{error::Error, sync::Arc, time::Duration};
 
 use docbox_core::aws::aws_config;
 use eyre::Context;
@@ -7,7 +7,17 @@ use tauri::{
     App, Manager,
 };
 
-use crate::{gateway::handle_gateway_request, server::ServerStore};
+use crate::{
+    database::{
+        entity::setting::Setting,
+        migrations::{migrate, MigrationStatus},
+    },
+    gateway::{stream::StreamServer, Gateway},
+    server::{
+        lock::{run_idle_lock, IdleLock, DEFAULT_IDLE_TIMEOUT, IDLE_TIMEOUT_SETTING},
+        ServerStore,
+    },
+};
 
 pub mod commands;
 pub mod database;
@@ -19,16 +29,33 @@ pub mod utils;
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     use commands::{
+        app::{
+            app_check_migrations, app_get_idle_timeout, app_list_backups, app_lock,
+            app_report_activity, app_restore_backup, app_rollback_migration, app_set_idle_timeout,
+        },
+        gateway::{
+            gateway_cancel, gateway_clear_history, gateway_get_history,
+            gateway_har_start_recording, gateway_har_start_replay, gateway_har_stop_recording,
+            gateway_har_stop_replay,
+        },
         root::{
             root_apply_migrations, root_get_pending_migrations, root_initialize,
             root_is_initialized,
         },
         server::{
-            server_create, server_delete, server_get_active, server_get_all, server_is_active,
-            server_load, server_unload,
+            server_change_password, server_create, server_decrypt_config, server_delete,
+            server_encrypt_config, server_export, server_get_active, server_get_all,
+            server_health_check, server_import, server_import_preview, server_is_active,
+            server_is_insecure, server_load, server_reorder, server_set_key_file,
+            server_set_read_only, server_unload, server_update, server_validate_config,
+            server_verify_password,
         },
         tenant::{tenant_create, tenant_delete, tenant_get, tenant_get_all, tenant_migrate},
-        utils::utils_encrypt,
+        utils::{utils_encrypt, utils_generate_key_file},
+        vault::{
+            vault_create, vault_disable, vault_lock, vault_migrate_in, vault_migrate_out,
+            vault_status, vault_unlock,
+        },
     };
 
     tauri::Builder::default()
@@ -36,22 +63,36 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .register_asynchronous_uri_scheme_protocol("docbox", |ctx, request, responder| {
             let app = ctx.app_handle();
-            let server_store = app.state::<Arc<ServerStore>>().inner().clone();
+            let gateway = app.state::<Arc<Gateway>>().inner().clone();
 
             spawn(async move {
-                let response = handle_gateway_request(server_store, request).await;
+                let response = gateway.handle_request(request).await;
                 responder.respond(response);
             });
         })
         .setup(setup)
-        .invoke_handler(tauri::generate_handler![
+        .invoke_handler(commands::require_migrated(tauri::generate_handler![
             server_create,
             server_get_all,
             server_load,
+            server_verify_password,
             server_unload,
             server_is_active,
+            server_is_insecure,
             server_get_active,
             server_delete,
+            server_set_read_only,
+            server_health_check,
+            server_validate_config,
+            server_update,
+            server_reorder,
+            server_change_password,
+            server_encrypt_config,
+            server_decrypt_config,
+            server_set_key_file,
+            server_export,
+            server_import_preview,
+            server_import,
             root_is_initialized,
             root_initialize,
             root_get_pending_migrations,
@@ -61,8 +102,31 @@ pub fn run() {
             tenant_get,
             tenant_get_all,
             tenant_migrate,
-            utils_encrypt
-        ])
+            utils_encrypt,
+            utils_generate_key_file,
+            gateway_get_history,
+            gateway_clear_history,
+            gateway_har_start_recording,
+            gateway_har_stop_recording,
+            gateway_har_start_replay,
+            gateway_har_stop_replay,
+            gateway_cancel,
+            app_lock,
+            app_report_activity,
+            app_get_idle_timeout,
+            app_set_idle_timeout,
+            app_list_backups,
+            app_restore_backup,
+            app_check_migrations,
+            app_rollback_migration,
+            vault_status,
+            vault_create,
+            vault_unlock,
+            vault_lock,
+            vault_disable,
+            vault_migrate_in,
+            vault_migrate_out
+        ]))
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -78,7 +142,9 @@ fn setup(app: &mut App) -> Result<(), Box<dyn Error>> {
         .app_data_dir()
         .context("failed to get app data dir")?;
 
-    let db = match block_on(database::connect_database(app_data_path.join("app.db"))) {
+    let db = match block_on(database::connect_database(
+        app_data_path.join(database::DATABASE_FILE_NAME),
+    )) {
         Ok(value) => value,
         Err(cause) => {
             tracing::error!(?cause, "failed to load database");
@@ -86,14 +152,54 @@ fn setup(app: &mut App) -> Result<(), Box<dyn Error>> {
         }
     };
 
+    // Migration failures are reported to the UI rather than exiting so that
+    // a backup can still be restored
+    let migration_status = match block_on(migrate(&db)) {
+        Ok(()) => MigrationStatus::default(),
+        Err(cause) => {
+            tracing::error!(?cause, "failed to run database migrations");
+            MigrationStatus::failed(cause)
+        }
+    };
+
     // Load AWS configuration
     let aws_config = block_on(aws_config());
 
     let store = Arc::new(ServerStore::default());
 
+    // Load the idle lock timeout, using the default when not configured
+    let idle_timeout = match block_on(Setting::get::<Option<u64>>(&db, IDLE_TIMEOUT_SETTING)) {
+        Ok(Some(timeout)) => timeout.map(Duration::from_secs),
+        Ok(None) => DEFAULT_IDLE_TIMEOUT,
+        Err(cause) => {
+            tracing::error!(?cause, "failed to load idle lock timeout");
+            DEFAULT_IDLE_TIMEOUT
+        }
+    };
+
+    let idle_lock = Arc::new(IdleLock::new(idle_timeout));
+    spawn(run_idle_lock(
+        app.handle().clone(),
+        idle_lock.clone(),
+        store.clone(),
+    ));
+
+    // Start the loopback server for streaming large gateway responses
+    let streams = block_on(StreamServer::bind()).context("failed to bind stream server")?;
+    let gateway = Arc::new(Gateway::new(
+        app.handle().clone(),
+        db.clone(),
+        store.clone(),
+        streams,
+        idle_lock.clone(),
+    ));
+
     app.manage(aws_config);
     app.manage(store);
+    app.manage(gateway);
+    app.manage(idle_lock);
     app.manage(db);
+    app.manage(migration_status);
 
     Ok(())
 }
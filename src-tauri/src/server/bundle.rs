@@ -0,0 +1,290 @@
+//! Password encrypted bundles of server definitions for sharing servers
+//! between installations
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        entity::server::{CreateServer, Server, ServerConfig, ServerId},
+        DbErr, DbPool,
+    },
+    utils::encryption::{decrypt, encrypt, EncryptError, EncryptedContent},
+};
+
+/// Identifies a file as a server bundle
+const BUNDLE_FORMAT: &str = "docbox-manager-servers";
+
+/// Current version of the bundle format
+const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum BundleError {
+    #[error("file is not a server bundle")]
+    InvalidBundle,
+
+    #[error("unsupported server bundle version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("bundle password is incorrect")]
+    IncorrectPassword,
+
+    #[error("cannot rename server to \"{0}\", the name is empty or already in use")]
+    InvalidRename(String),
+
+    #[error("failed to encrypt bundle: {0}")]
+    Encrypt(#[from] EncryptError),
+
+    #[error("failed to serialize bundle: {0}")]
+    Serialize(serde_json::Error),
+
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// File written to disk, the servers are only present in encrypted form
+#[derive(Serialize, Deserialize)]
+struct BundleFile {
+    format: String,
+    version: u32,
+    #[serde(flatten)]
+    content: EncryptedContent,
+}
+
+/// Decrypted contents of a bundle
+#[derive(Serialize, Deserialize)]
+pub struct ServerBundle {
+    pub servers: Vec<BundledServer>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BundledServer {
+    pub id: ServerId,
+    pub name: String,
+    pub config: ServerConfig,
+    pub read_only: bool,
+}
+
+/// Server from a bundle along with any conflict with an existing server
+#[derive(Serialize)]
+pub struct ImportCandidate {
+    pub id: ServerId,
+    pub name: String,
+    pub conflict: Option<ImportConflict>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImportConflict {
+    /// A server with the same ID already exists
+    Id { existing_name: String },
+    /// A different server already uses the name
+    Name { existing_id: ServerId },
+}
+
+/// How to handle a server that conflicts with an existing one
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImportResolution {
+    /// Keep the existing server and ignore the bundled one
+    Skip,
+    /// Import the bundled server as a new server with a different name
+    Rename { name: String },
+    /// Replace the existing server with the bundled one
+    Overwrite,
+}
+
+/// Result of importing a bundle
+#[derive(Default, Serialize)]
+pub struct ImportOutcome {
+    pub imported: Vec<ServerId>,
+    pub skipped: Vec<ServerId>,
+    /// Existing servers that were replaced and must be unloaded
+    pub replaced: Vec<ServerId>,
+}
+
+impl ServerBundle {
+    pub fn from_servers(servers: Vec<Server>) -> ServerBundle {
+        ServerBundle {
+            servers: servers
+                .into_iter()
+                .map(|server| BundledServer {
+                    id: server.id,
+                    name: server.name,
+                    config: server.config,
+                    read_only: server.read_only,
+                })
+                .collect(),
+        }
+    }
+
+    /// Encrypt the bundle with `password` into the bytes of a bundle file
+    pub fn encrypt(&self, password: &str) -> Result<Vec<u8>, BundleError> {
+        let data = serde_json::to_vec(self).map_err(BundleError::Serialize)?;
+        let content = encrypt(password.as_bytes(), None, &data)?;
+
+        serde_json::to_vec_pretty(&BundleFile {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            content,
+        })
+        .map_err(BundleError::Serialize)
+    }
+
+    /// Decrypt the bytes of a bundle file using `password`
+    pub fn decrypt(file: &[u8], password: &str) -> Result<ServerBundle, BundleError> {
+        let file: BundleFile =
+            serde_json::from_slice(file).map_err(|_| BundleError::InvalidBundle)?;
+
+        if file.format != BUNDLE_FORMAT {
+            return Err(BundleError::InvalidBundle);
+        }
+
+        if file.version != BUNDLE_VERSION {
+            return Err(BundleError::UnsupportedVersion(file.version));
+        }
+
+        // Bundles come from outside the app so the envelope is checked first
+        if !file.content.is_well_formed() {
+            return Err(BundleError::InvalidBundle);
+        }
+
+        let data = decrypt(password.as_bytes(), None, &file.content)
+            .map_err(|_| BundleError::IncorrectPassword)?;
+
+        let mut bundle: ServerBundle =
+            serde_json::from_slice(&data).map_err(|_| BundleError::InvalidBundle)?;
+
+        // Only the first server with each ID is kept, duplicates would fail
+        // the whole import
+        let mut seen = HashSet::new();
+        bundle.servers.retain(|server| seen.insert(server.id));
+
+        Ok(bundle)
+    }
+
+    /// Find conflicts between the bundled servers and the `existing` servers
+    pub fn candidates(&self, existing: &[Server]) -> Vec<ImportCandidate> {
+        self.servers
+            .iter()
+            .map(|server| ImportCandidate {
+                id: server.id,
+                name: server.name.clone(),
+                conflict: find_conflict(server, existing),
+            })
+            .collect()
+    }
+
+    /// Import the bundled servers in a single transaction, conflicting
+    /// servers without a resolution are skipped
+    pub async fn import(
+        self,
+        db: &DbPool,
+        mut resolutions: HashMap<ServerId, ImportResolution>,
+    ) -> Result<ImportOutcome, BundleError> {
+        let existing = Server::all(db).await?;
+        let mut order = existing
+            .iter()
+            .map(|server| server.order + 1)
+            .max()
+            .unwrap_or_default();
+
+        // Names of the existing and imported servers, rename targets must
+        // not reuse any of these
+        let mut names: HashSet<String> =
+            existing.iter().map(|server| server.name.clone()).collect();
+
+        let mut outcome = ImportOutcome::default();
+        let mut tx = db.begin().await?;
+
+        for server in self.servers {
+            let conflict = find_conflict(&server, &existing);
+            let resolution = match conflict {
+                Some(_) => resolutions
+                    .remove(&server.id)
+                    .unwrap_or(ImportResolution::Skip),
+                None => ImportResolution::Overwrite,
+            };
+
+            let (id, name) = match resolution {
+                ImportResolution::Skip => {
+                    outcome.skipped.push(server.id);
+                    continue;
+                }
+                ImportResolution::Rename { name } => {
+                    if name.trim().is_empty() || names.contains(&name) {
+                        return Err(BundleError::InvalidRename(name));
+                    }
+
+                    // Conflicting IDs are replaced so the server is imported as a new server
+                    let id = match conflict {
+                        Some(ImportConflict::Id { .. }) => Uuid::new_v4(),
+                        _ => server.id,
+                    };
+
+                    (id, name)
+                }
+                ImportResolution::Overwrite => {
+                    // Only the server the bundled server conflicts with is replaced
+                    let replaced = match &conflict {
+                        Some(ImportConflict::Id { .. }) => Some(server.id),
+                        Some(ImportConflict::Name { existing_id }) => Some(*existing_id),
+                        None => None,
+                    };
+
+                    if let Some(replaced) = replaced {
+                        if let Some(replaced) =
+                            existing.iter().find(|existing| existing.id == replaced)
+                        {
+                            names.remove(&replaced.name);
+                        }
+
+                        Server::delete_by_id(&mut *tx, replaced).await?;
+                        outcome.replaced.push(replaced);
+                    }
+
+                    (server.id, server.name)
+                }
+            };
+
+            names.insert(name.clone());
+
+            Server::create(
+                &mut *tx,
+                CreateServer {
+                    id,
+                    name,
+                    config: server.config,
+                    order,
+                    read_only: server.read_only,
+                },
+            )
+            .await?;
+
+            order += 1;
+            outcome.imported.push(id);
+        }
+
+        tx.commit().await?;
+
+        Ok(outcome)
+    }
+}
+
+fn find_conflict(server: &BundledServer, existing: &[Server]) -> Option<ImportConflict> {
+    if let Some(existing) = existing.iter().find(|existing| existing.id == server.id) {
+        return Some(ImportConflict::Id {
+            existing_name: existing.name.clone(),
+        });
+    }
+
+    existing
+        .iter()
+        .find(|existing| existing.name == server.name)
+        .map(|existing| ImportConflict::Name {
+            existing_id: existing.id,
+        })
+}
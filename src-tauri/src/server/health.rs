@@ -0,0 +1,207 @@
+//! Health checks for the components of a loaded server
+
+use std::{fmt::Display, future::Future, time::Duration};
+
+use docbox_database::models::tenant::Tenant;
+use docbox_management::database::DatabaseProvider as _;
+use reqwest::{Method, StatusCode};
+use serde::Serialize;
+use tokio::time::Instant;
+
+use super::ActiveServer;
+
+/// Time each component has to respond before it is considered unhealthy
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Health of each component of a server
+#[derive(Debug, Serialize)]
+pub struct ServerHealth {
+    pub api: ComponentHealth,
+    pub database: ComponentHealth,
+    pub secrets: ComponentHealth,
+    pub search: ComponentHealth,
+    pub storage: ComponentHealth,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Healthy,
+    Unhealthy,
+    /// Component could not be checked, such as search and storage when the
+    /// server has no tenants
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+impl ComponentHealth {
+    fn skipped(reason: &str) -> ComponentHealth {
+        ComponentHealth {
+            status: HealthStatus::Skipped,
+            latency_ms: 0,
+            error: Some(reason.to_string()),
+        }
+    }
+}
+
+impl ActiveServer {
+    /// Probe each component of the server independently
+    pub async fn health_check(&self) -> ServerHealth {
+        let (api, database, secrets, tenant) = tokio::join!(
+            probe(self.check_api()),
+            probe(self.check_database()),
+            probe(self.check_secrets()),
+            self.first_tenant(),
+        );
+
+        // Search indexes and storage buckets belong to tenants so a tenant is
+        // required to check them
+        let (search, storage) = match tenant {
+            Ok(Some(tenant)) => {
+                tokio::join!(
+                    probe(self.check_search(&tenant)),
+                    probe(self.check_storage(&tenant)),
+                )
+            }
+            Ok(None) => (
+                ComponentHealth::skipped("server has no tenants to check"),
+                ComponentHealth::skipped("server has no tenants to check"),
+            ),
+            Err(error) => {
+                let reason = format!("failed to get tenants to check: {error}");
+                (
+                    ComponentHealth::skipped(&reason),
+                    ComponentHealth::skipped(&reason),
+                )
+            }
+        };
+
+        ServerHealth {
+            api,
+            database,
+            secrets,
+            search,
+            storage,
+        }
+    }
+
+    /// Check the API is reachable and accepts the configured credentials
+    async fn check_api(&self) -> Result<(), String> {
+        let response = self
+            .api_request(Method::GET, &self.config.api.url)
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(format!("api rejected the credentials ({status})"));
+        }
+
+        if status.is_server_error() {
+            return Err(format!("api responded with an error ({status})"));
+        }
+
+        Ok(())
+    }
+
+    /// Check the root database accepts connections and queries
+    async fn check_database(&self) -> Result<(), String> {
+        let db = self
+            .db_provider
+            .connect(docbox_database::ROOT_DATABASE_NAME)
+            .await
+            .map_err(|error| error.to_string())?;
+
+        let result = docbox_database::sqlx::query("SELECT 1")
+            .execute(&db)
+            .await
+            .map(|_| ())
+            .map_err(|error| error.to_string());
+
+        db.close().await;
+        result
+    }
+
+    /// Check the root database secret can be read
+    async fn check_secrets(&self) -> Result<(), String> {
+        self.secrets
+            .parsed_secret::<serde_json::Value>(&self.config.database.root_secret_name)
+            .await
+            .map_err(|error| error.to_string())?
+            .ok_or_else(|| "root database secret not found".to_string())?;
+
+        Ok(())
+    }
+
+    /// Check the search index of a tenant exists
+    async fn check_search(&self, tenant: &Tenant) -> Result<(), String> {
+        let index = self.search.create_search_index(tenant);
+        check_exists(index.index_exists().await, "tenant search index not found")
+    }
+
+    /// Check the storage bucket of a tenant exists
+    async fn check_storage(&self, tenant: &Tenant) -> Result<(), String> {
+        let storage = self.storage.create_storage_layer(tenant);
+        check_exists(
+            storage.bucket_exists().await,
+            "tenant storage bucket not found",
+        )
+    }
+
+    /// Find a tenant to check the tenant components against, the lookup is
+    /// bound by the health check timeout
+    async fn first_tenant(&self) -> Result<Option<Tenant>, String> {
+        let tenants = tokio::time::timeout(
+            HEALTH_CHECK_TIMEOUT,
+            docbox_management::tenant::get_tenants::get_tenants(&self.db_provider),
+        )
+        .await
+        .map_err(|_| "tenant lookup timed out".to_string())?
+        .map_err(|error| {
+            tracing::warn!(?error, "failed to get tenants for health check");
+            error.to_string()
+        })?;
+
+        Ok(tenants.into_iter().next())
+    }
+}
+
+/// Time a component check, applying the health check timeout
+async fn probe<F>(check: F) -> ComponentHealth
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let start = Instant::now();
+    let result = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, check).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(error)) => Some(error),
+        Err(_) => Some("health check timed out".to_string()),
+    };
+
+    ComponentHealth {
+        status: match error {
+            Some(_) => HealthStatus::Unhealthy,
+            None => HealthStatus::Healthy,
+        },
+        latency_ms,
+        error,
+    }
+}
+
+fn check_exists<E: Display>(result: Result<bool, E>, missing: &str) -> Result<(), String> {
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(missing.to_string()),
+        Err(error) => Err(error.to_string()),
+    }
+}
@@ -0,0 +1,112 @@
+//! Idle lock, unloads password protected servers after a period of
+//! inactivity so their decrypted configs do not remain in memory
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::{database::entity::server::ServerId, gateway::Gateway};
+
+use super::ServerStore;
+
+/// Event emitted to the webview when servers are unloaded by the lock
+pub const LOCKED_EVENT: &str = "app:locked";
+
+/// Setting storing the idle timeout in seconds, `null` disables the lock
+pub const IDLE_TIMEOUT_SETTING: &str = "idle_lock_timeout";
+
+/// Idle timeout used when none has been configured, the lock is disabled
+/// until enabled using [IDLE_TIMEOUT_SETTING]. Activity is reported by the
+/// webview on user input and by requests through the gateway
+pub const DEFAULT_IDLE_TIMEOUT: Option<Duration> = None;
+
+/// Interval between checks for inactivity
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+pub struct IdleLock {
+    last_activity: Mutex<Instant>,
+    /// Period of inactivity before locking, [None] when disabled
+    timeout: Mutex<Option<Duration>>,
+}
+
+impl IdleLock {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            last_activity: Mutex::new(Instant::now()),
+            timeout: Mutex::new(timeout),
+        }
+    }
+
+    /// Record user activity, resetting the idle period
+    pub fn touch(&self) {
+        *self
+            .last_activity
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = Instant::now();
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        *self.timeout.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        *self.timeout.lock().unwrap_or_else(|err| err.into_inner()) = timeout;
+        self.touch();
+    }
+
+    fn is_idle(&self) -> bool {
+        let Some(timeout) = self.timeout() else {
+            return false;
+        };
+
+        let last_activity = *self
+            .last_activity
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        last_activity.elapsed() >= timeout
+    }
+}
+
+/// Lock the vault and unload all password protected servers then notify
+/// the webview, returns the IDs of the unloaded servers. Anything the gateway
+/// holds for the unloaded servers is dropped along with them
+pub async fn lock_servers(app: &AppHandle, store: &ServerStore) -> Vec<ServerId> {
+    store.vault().lock();
+    let unloaded = store.remove_password_protected().await;
+
+    if let Some(gateway) = app.try_state::<Arc<Gateway>>() {
+        for server_id in &unloaded {
+            gateway.purge_server(*server_id).await;
+        }
+    }
+
+    if !unloaded.is_empty() {
+        tracing::info!(
+            servers = unloaded.len(),
+            "locked password protected servers"
+        );
+
+        if let Err(error) = app.emit(LOCKED_EVENT, &unloaded) {
+            tracing::warn!(?error, "failed to emit locked event");
+        }
+    }
+
+    unloaded
+}
+
+/// Background task locking the app once it has been idle for the timeout
+pub async fn run_idle_lock(app: AppHandle, lock: Arc<IdleLock>, store: Arc<ServerStore>) {
+    let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if lock.is_idle() {
+            lock_servers(&app, &store).await;
+        }
+    }
+}
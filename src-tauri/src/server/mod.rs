@@ -1,30 +1,58 @@
-use std::{collections::HashMap, future::Future, sync::Arc};
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use aws_config::SdkConfig;
-use docbox_database::{DatabasePoolCache, DatabasePoolCacheConfig};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use docbox_database::{sqlx::postgres::PgSslMode, DatabasePoolCache, DatabasePoolCacheConfig};
 use docbox_search::{SearchIndexFactory, SearchIndexFactoryError};
 use docbox_secrets::{SecretManager, SecretManagerError, SecretsManagerConfig};
 use docbox_storage::StorageLayerFactory;
 use moka::future::Cache;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION},
+    Method,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::Mutex;
+use vault::{Vault, VaultError};
+use zeroize::Zeroizing;
 
 use crate::{
     database::entity::server::{
-        AdminDatabaseConfiguration, AdminDatabaseSetupUserConfig, Server, ServerConfig,
-        ServerConfigData, ServerId,
+        AdminDatabaseConfiguration, AdminDatabaseSetupUserConfig, ApiAuth, ApiConfig, Server,
+        ServerConfig, ServerConfigData, ServerId, TlsConfig,
     },
-    utils::encryption::decrypt,
+    utils::{encryption::decrypt, key_file::read_key_file, secret::SecretString},
 };
 
+pub mod bundle;
+pub mod health;
+pub mod lock;
+pub mod validate;
+pub mod vault;
+
 /// Active server connections
 #[derive(Default)]
 pub struct ServerStore {
     servers: Mutex<HashMap<ServerId, Arc<ActiveServer>>>,
+    vault: Vault,
 }
 
 impl ServerStore {
+    /// Master password vault for server configs
+    pub fn vault(&self) -> &Vault {
+        &self.vault
+    }
+
     pub async fn try_load_server(
         &self,
         aws_config: &SdkConfig,
@@ -32,7 +60,7 @@ impl ServerStore {
         load_config: LoadServerConfig,
     ) -> Result<Arc<ActiveServer>, LoadServerError> {
         let servers = &mut *self.servers.lock().await;
-        let server = load_server(aws_config, server, load_config).await?;
+        let server = load_server(aws_config, &self.vault, server, load_config).await?;
         let server = Arc::new(server);
         servers.insert(server.id, server.clone());
         Ok(server)
@@ -49,6 +77,23 @@ impl ServerStore {
     pub async fn remove_server(&self, server_id: ServerId) {
         self.servers.lock().await.remove(&server_id);
     }
+
+    /// Unload all servers that required a password to load, returns the
+    /// IDs of the unloaded servers
+    pub async fn remove_password_protected(&self) -> Vec<ServerId> {
+        let servers = &mut *self.servers.lock().await;
+        let protected: Vec<ServerId> = servers
+            .values()
+            .filter(|server| server.password_protected)
+            .map(|server| server.id)
+            .collect();
+
+        for server_id in &protected {
+            servers.remove(server_id);
+        }
+
+        protected
+    }
 }
 
 #[derive(Debug, Error)]
@@ -59,6 +104,12 @@ pub enum LoadServerError {
     #[error("server config password is incorrect")]
     IncorrectPassword,
 
+    #[error("server config requires a key file")]
+    MissingKeyFile,
+
+    #[error("failed to read key file: {0}")]
+    ReadKeyFile(std::io::Error),
+
     #[error("failed to load server config secret: {0}")]
     SecretManager(#[from] SecretManagerError),
 
@@ -76,47 +127,51 @@ pub enum LoadServerError {
 
     #[error("failed to deserialize config")]
     Deserialize(serde_json::Error),
+
+    #[error("failed to create api http client: {0}")]
+    CreateHttpClient(reqwest::Error),
+
+    #[error("invalid api authentication header: {0}")]
+    InvalidApiHeader(String),
+
+    #[error(transparent)]
+    Vault(#[from] VaultError),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LoadServerConfig {
-    pub password: Option<String>,
+    pub password: Option<SecretString>,
+    /// Path to the key file for configs that require one
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
 }
 
 pub async fn load_server(
     aws_config: &SdkConfig,
+    vault: &Vault,
     server: Server,
     load_config: LoadServerConfig,
 ) -> Result<ActiveServer, LoadServerError> {
-    let config: ServerConfigData = match server.config {
-        // Load secret from AWS
-        ServerConfig::AwsSecret { secret_name } => {
-            let secrets = SecretManager::from_config(aws_config, SecretsManagerConfig::Aws);
-            secrets
-                .parsed_secret(&secret_name)
-                .await?
-                .ok_or(LoadServerError::MissingSecret)?
-        }
-
-        // Secret is directly available
-        ServerConfig::Config { data } => data,
-
-        // Secret must be decrypted
-        ServerConfig::Encrypted { salt, nonce, data } => {
-            let password = match load_config.password {
-                Some(value) => value,
-                None => return Err(LoadServerError::MissingPassword),
-            };
-
-            // Decrypt the content
-            let decrypted = match decrypt(password.as_bytes(), &salt, &nonce, &data) {
-                Ok(value) => value,
-                Err(_) => return Err(LoadServerError::IncorrectPassword),
-            };
+    let password_protected = matches!(
+        server.config,
+        ServerConfig::Encrypted(_) | ServerConfig::Vault { .. }
+    );
+    let config = load_server_config(
+        aws_config,
+        vault,
+        server.config,
+        load_config.password.as_deref().map(String::as_str),
+        load_config.key_file.as_deref(),
+    )
+    .await?;
+
+    if config.api.tls.accept_invalid_certificates {
+        tracing::warn!(server_id = %server.id, "server accepts invalid certificates");
+    }
 
-            serde_json::from_slice(&decrypted).map_err(LoadServerError::Deserialize)?
-        }
-    };
+    // Setup the pooled API client
+    let http = create_http_client(&config.api).map_err(LoadServerError::CreateHttpClient)?;
+    let api_headers = create_api_headers(&config.api)?;
 
     // Setup server secret manager
     let secrets = SecretManager::from_config(aws_config, config.secrets.clone());
@@ -152,6 +207,7 @@ pub async fn load_server(
             config: config.database.clone(),
             username: setup_user.username.clone(),
             password: setup_user.password.clone(),
+            tls: config.api.tls.clone(),
         },
         (_, Some(setup_user_secret_name)) => {
             let secret: AdminDatabaseSetupUserConfig = secrets
@@ -165,6 +221,7 @@ pub async fn load_server(
                 config: config.database.clone(),
                 username: secret.username.clone(),
                 password: secret.password.clone(),
+                tls: config.api.tls.clone(),
             }
         }
         (None, None) => {
@@ -175,7 +232,11 @@ pub async fn load_server(
     Ok(ActiveServer {
         id: server.id,
         name: server.name,
+        read_only: AtomicBool::new(server.read_only),
+        password_protected,
         config,
+        http,
+        api_headers,
         db_provider,
         db_cache,
         secrets,
@@ -184,10 +245,77 @@ pub async fn load_server(
     })
 }
 
+/// Load the config data for a server, fetching or decrypting it when required
+pub async fn load_server_config(
+    aws_config: &SdkConfig,
+    vault: &Vault,
+    config: ServerConfig,
+    password: Option<&str>,
+    key_file: Option<&Path>,
+) -> Result<ServerConfigData, LoadServerError> {
+    let config: ServerConfigData = match config {
+        // Load secret from AWS
+        ServerConfig::AwsSecret { secret_name } => {
+            let secrets = SecretManager::from_config(aws_config, SecretsManagerConfig::Aws);
+            secrets
+                .parsed_secret(&secret_name)
+                .await?
+                .ok_or(LoadServerError::MissingSecret)?
+        }
+
+        // Secret is directly available
+        ServerConfig::Config { data } => data,
+
+        // Secret must be decrypted
+        ServerConfig::Encrypted(content) => {
+            let password = match password {
+                Some(value) => value,
+                None => return Err(LoadServerError::MissingPassword),
+            };
+
+            let key_file = match (content.key_file, key_file) {
+                (true, Some(path)) => Some(
+                    read_key_file(path)
+                        .await
+                        .map_err(LoadServerError::ReadKeyFile)?,
+                ),
+                (true, None) => return Err(LoadServerError::MissingKeyFile),
+                (false, _) => None,
+            };
+
+            // Decrypt the content
+            let decrypted = match decrypt(
+                password.as_bytes(),
+                key_file.as_deref().map(Vec::as_slice),
+                &content,
+            ) {
+                Ok(value) => Zeroizing::new(value),
+                Err(_) => return Err(LoadServerError::IncorrectPassword),
+            };
+
+            serde_json::from_slice(&decrypted).map_err(LoadServerError::Deserialize)?
+        }
+
+        // Secret must be decrypted using the unlocked vault
+        ServerConfig::Vault { nonce, data } => vault.open(&nonce, &data)?,
+    };
+
+    Ok(config)
+}
+
 pub struct ActiveServer {
     pub id: ServerId,
     pub name: String,
+    /// Whether mutating requests and destructive commands are blocked
+    read_only: AtomicBool,
+    /// Whether the config was decrypted using a password, these servers are
+    /// unloaded when the app locks
+    pub password_protected: bool,
     pub config: ServerConfigData,
+    /// Pooled HTTP client for the docbox API
+    pub http: reqwest::Client,
+    /// Authentication headers sent with every API request
+    pub api_headers: HeaderMap,
     //
     pub db_provider: DatabaseProvider,
     //
@@ -197,10 +325,157 @@ pub struct ActiveServer {
     pub storage: StorageLayerFactory,
 }
 
+/// Default timeout for connecting to the docbox API
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default timeout between reads from the docbox API
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default maximum number of idle pooled connections
+const DEFAULT_POOL_MAX_IDLE: usize = 8;
+
+/// Default user agent for requests to the docbox API
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Create the HTTP client used for requests to the docbox API
+fn create_http_client(config: &ApiConfig) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(
+            config
+                .connect_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+        )
+        .read_timeout(
+            config
+                .read_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_READ_TIMEOUT),
+        )
+        .pool_max_idle_per_host(config.pool_max_idle.unwrap_or(DEFAULT_POOL_MAX_IDLE))
+        .user_agent(config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT));
+
+    for root_certificate in &config.tls.root_certificates {
+        for certificate in reqwest::Certificate::from_pem_bundle(root_certificate.as_bytes())? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if config.tls.accept_invalid_certificates {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    if let Some(proxy_config) = config.proxy.as_ref() {
+        let mut proxy = reqwest::Proxy::all(&proxy_config.url)?;
+
+        if let Some(username) = proxy_config.username.as_deref() {
+            proxy = proxy.basic_auth(
+                username,
+                proxy_config
+                    .password
+                    .as_deref()
+                    .map(String::as_str)
+                    .unwrap_or_default(),
+            );
+        }
+
+        if let Some(no_proxy) = proxy_config.no_proxy.as_deref() {
+            proxy = proxy.no_proxy(reqwest::NoProxy::from_string(no_proxy));
+        }
+
+        builder = builder.proxy(proxy);
+    }
+
+    for auth in &config.auth {
+        if let ApiAuth::ClientCertificate {
+            certificate_pem,
+            key_pem,
+        } = auth
+        {
+            let identity =
+                reqwest::Identity::from_pkcs8_pem(certificate_pem.as_bytes(), key_pem.as_bytes())?;
+            builder = builder.identity(identity);
+        }
+    }
+
+    builder.build()
+}
+
+/// Create the authentication headers sent with every docbox API request
+fn create_api_headers(config: &ApiConfig) -> Result<HeaderMap, LoadServerError> {
+    let mut headers = HeaderMap::new();
+
+    let mut insert = |name: &str, value: &str| -> Result<(), LoadServerError> {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| LoadServerError::InvalidApiHeader(name.to_string()))?;
+        let mut value = HeaderValue::from_str(value)
+            .map_err(|_| LoadServerError::InvalidApiHeader(name.to_string()))?;
+        value.set_sensitive(true);
+        headers.insert(name, value);
+        Ok(())
+    };
+
+    if let Some(api_key) = config.api_key.as_deref() {
+        insert("x-docbox-api-key", api_key.as_str())?;
+    }
+
+    for auth in &config.auth {
+        match auth {
+            ApiAuth::Bearer { token } => {
+                insert(
+                    AUTHORIZATION.as_str(),
+                    &format!("Bearer {}", token.as_str()),
+                )?;
+            }
+            ApiAuth::Basic { username, password } => {
+                let credentials = Zeroizing::new(format!(
+                    "{username}:{}",
+                    password.as_deref().map(String::as_str).unwrap_or_default()
+                ));
+                let credentials = BASE64_STANDARD.encode(credentials.as_bytes());
+                insert(AUTHORIZATION.as_str(), &format!("Basic {credentials}"))?;
+            }
+            ApiAuth::Headers { headers } => {
+                for (name, value) in headers {
+                    insert(name, value)?;
+                }
+            }
+            // Applied to the client during the TLS handshake
+            ApiAuth::ClientCertificate { .. } => {}
+        }
+    }
+
+    Ok(headers)
+}
+
+impl ActiveServer {
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Acquire)
+    }
+
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Release);
+    }
+
+    /// Whether certificate verification is disabled for the server, the UI
+    /// must make this clearly visible
+    pub fn accepts_invalid_certificates(&self) -> bool {
+        self.config.api.tls.accept_invalid_certificates
+    }
+
+    /// Create a request to the docbox API with the configured authentication
+    pub fn api_request(&self, method: Method, url: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, url)
+            .headers(self.api_headers.clone())
+    }
+}
+
 pub struct DatabaseProvider {
     pub config: AdminDatabaseConfiguration,
     pub username: String,
-    pub password: String,
+    pub password: SecretString,
+    pub tls: TlsConfig,
 }
 
 impl docbox_management::database::DatabaseProvider for DatabaseProvider {
@@ -208,13 +483,22 @@ impl docbox_management::database::DatabaseProvider for DatabaseProvider {
         &self,
         database: &str,
     ) -> impl Future<Output = docbox_database::DbResult<docbox_database::DbPool>> + Send {
-        let options = docbox_database::PgConnectOptions::new()
+        let mut options = docbox_database::PgConnectOptions::new()
             .host(&self.config.host)
             .port(self.config.port)
             .username(&self.username)
             .password(&self.password)
             .database(database);
 
+        if self.tls.accept_invalid_certificates {
+            // Require encryption without verifying the certificate
+            options = options.ssl_mode(PgSslMode::Require);
+        } else if !self.tls.root_certificates.is_empty() {
+            options = options
+                .ssl_mode(PgSslMode::VerifyFull)
+                .ssl_root_cert_from_pem(self.tls.root_certificates.join("\n").into_bytes());
+        }
+
         docbox_database::sqlx::PgPool::connect_with(options)
     }
 }
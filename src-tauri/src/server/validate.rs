@@ -0,0 +1,227 @@
+//! Validation of a server configuration before it is saved
+
+use std::path::PathBuf;
+
+use aws_config::SdkConfig;
+use serde::Serialize;
+
+use crate::{
+    database::entity::server::{ApiAuth, CreateServer, Server, ServerConfig, ServerConfigData},
+    utils::secret::SecretString,
+};
+
+use super::{
+    create_api_headers, create_http_client,
+    health::{HealthStatus, ServerHealth},
+    load_server, load_server_config,
+    vault::Vault,
+    LoadServerConfig, LoadServerError,
+};
+
+/// Outcome of validating a server configuration
+#[derive(Debug, Default, Serialize)]
+pub struct ConfigValidation {
+    /// Problems with the configuration, empty when the configuration is valid
+    pub errors: Vec<FieldError>,
+    /// Health of the trial loaded server, only present when it could be loaded
+    pub health: Option<ServerHealth>,
+}
+
+/// Error for a specific field of the server form
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    /// Path to the field, config data fields are relative to the config data
+    /// such as `api.url` or `database.host`
+    pub field: String,
+    pub message: String,
+}
+
+impl ConfigValidation {
+    fn push(&mut self, field: &str, message: impl ToString) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        });
+    }
+}
+
+/// Validate a server by performing a trial load and probing each of its
+/// components, nothing is stored and the server is not made active
+pub async fn validate_server(
+    aws_config: &SdkConfig,
+    vault: &Vault,
+    create: CreateServer,
+    password: Option<SecretString>,
+    key_file: Option<PathBuf>,
+) -> ConfigValidation {
+    let mut validation = ConfigValidation::default();
+
+    if create.name.trim().is_empty() {
+        validation.push("name", "name is required");
+    }
+
+    let config_field = match &create.config {
+        ServerConfig::AwsSecret { secret_name } => {
+            if secret_name.trim().is_empty() {
+                validation.push("secret_name", "secret name is required");
+                return validation;
+            }
+
+            "secret_name"
+        }
+        ServerConfig::Config { .. } => "config",
+        ServerConfig::Encrypted(_) => "password",
+        ServerConfig::Vault { .. } => "vault",
+    };
+
+    let data = match load_server_config(
+        aws_config,
+        vault,
+        create.config,
+        password.as_deref().map(String::as_str),
+        key_file.as_deref(),
+    )
+    .await
+    {
+        Ok(value) => value,
+        Err(error) => {
+            let field = match &error {
+                LoadServerError::MissingKeyFile | LoadServerError::ReadKeyFile(_) => "key_file",
+                _ => config_field,
+            };
+
+            validation.push(field, error);
+            return validation;
+        }
+    };
+
+    validate_config_data(&data, &mut validation);
+
+    if !validation.errors.is_empty() {
+        return validation;
+    }
+
+    let server = Server {
+        id: create.id,
+        name: create.name,
+        config: ServerConfig::Config { data },
+        order: create.order,
+        read_only: create.read_only,
+    };
+
+    let server = match load_server(
+        aws_config,
+        vault,
+        server,
+        LoadServerConfig {
+            password: None,
+            key_file: None,
+        },
+    )
+    .await
+    {
+        Ok(value) => value,
+        Err(error) => {
+            validation.push(load_error_field(&error), error);
+            return validation;
+        }
+    };
+
+    let health = server.health_check().await;
+
+    for (field, component) in [
+        ("api.url", &health.api),
+        ("database.host", &health.database),
+        ("database.root_secret_name", &health.secrets),
+        ("search", &health.search),
+        ("storage", &health.storage),
+    ] {
+        if let (HealthStatus::Unhealthy, Some(error)) = (&component.status, &component.error) {
+            validation.push(field, error);
+        }
+    }
+
+    validation.health = Some(health);
+    validation
+}
+
+/// Checks that can be made against the config data without connecting to
+/// anything
+fn validate_config_data(data: &ServerConfigData, validation: &mut ConfigValidation) {
+    let api = &data.api;
+
+    match reqwest::Url::parse(&api.url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        Ok(_) => validation.push("api.url", "url must use http or https"),
+        Err(error) => validation.push("api.url", error),
+    }
+
+    for root_certificate in &api.tls.root_certificates {
+        if let Err(error) = reqwest::Certificate::from_pem_bundle(root_certificate.as_bytes()) {
+            validation.push("api.tls.root_certificates", error);
+        }
+    }
+
+    if let Some(proxy) = api.proxy.as_ref() {
+        if let Err(error) = reqwest::Proxy::all(&proxy.url) {
+            validation.push("api.proxy.url", error);
+        }
+    }
+
+    for auth in &api.auth {
+        if let ApiAuth::ClientCertificate {
+            certificate_pem,
+            key_pem,
+        } = auth
+        {
+            if let Err(error) =
+                reqwest::Identity::from_pkcs8_pem(certificate_pem.as_bytes(), key_pem.as_bytes())
+            {
+                validation.push("api.auth", error);
+            }
+        }
+    }
+
+    if let Err(error) = create_api_headers(api) {
+        validation.push("api.auth", error);
+    }
+
+    // Catch anything else the client rejects once the individual parts are valid
+    if validation.errors.is_empty() {
+        if let Err(error) = create_http_client(api) {
+            validation.push("api", error);
+        }
+    }
+
+    if data.database.host.trim().is_empty() {
+        validation.push("database.host", "host is required");
+    }
+
+    if data.database.root_secret_name.trim().is_empty() {
+        validation.push("database.root_secret_name", "root secret name is required");
+    }
+
+    if data.database.setup_user.is_none() && data.database.setup_user_secret_name.is_none() {
+        validation.push(
+            "database.setup_user",
+            LoadServerError::MissingSetupUser.to_string(),
+        );
+    }
+}
+
+/// Form field responsible for an error while loading the server
+fn load_error_field(error: &LoadServerError) -> &'static str {
+    match error {
+        LoadServerError::MissingPassword | LoadServerError::IncorrectPassword => "password",
+        LoadServerError::MissingKeyFile | LoadServerError::ReadKeyFile(_) => "key_file",
+        LoadServerError::SecretManager(_) => "secrets",
+        LoadServerError::MissingSecret => "secret_name",
+        LoadServerError::MissingDatabaseSecret => "database.setup_user_secret_name",
+        LoadServerError::MissingSetupUser => "database.setup_user",
+        LoadServerError::CreateSearchFactory(_) => "search",
+        LoadServerError::Deserialize(_) => "config",
+        LoadServerError::CreateHttpClient(_) => "api",
+        LoadServerError::InvalidApiHeader(_) => "api.auth",
+        LoadServerError::Vault(_) => "vault",
+    }
+}
@@ -0,0 +1,236 @@
+//! Master password vault, when enabled server configs are encrypted at rest
+//! using a key derived from a single master password that is unlocked once
+//! per session
+
+use std::sync::Mutex;
+
+use argon2::password_hash::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::{
+    database::{
+        entity::{
+            server::{Server, ServerConfig, ServerConfigData, UpdateServer},
+            setting::Setting,
+        },
+        DbErr, DbExecutor, DbPool,
+    },
+    utils::encryption::{
+        decrypt_with_key, derive_key, encrypt_with_key, DeriveKeyError, DerivedKey, KdfParams,
+    },
+};
+
+/// Setting storing the [VaultSettings], absent when the vault is disabled
+const VAULT_SETTING: &str = "vault";
+
+/// Known value encrypted with the vault key to check the master password
+const VAULT_VERIFIER: &[u8] = b"docbox-manager-vault";
+
+#[derive(Debug, Error)]
+pub enum VaultError {
+    #[error("vault is not enabled")]
+    NotEnabled,
+
+    #[error("vault is already enabled")]
+    AlreadyEnabled,
+
+    #[error("vault is locked")]
+    Locked,
+
+    #[error("vault master password is incorrect")]
+    IncorrectPassword,
+
+    #[error("failed to derive vault key")]
+    DeriveKey(DeriveKeyError),
+
+    #[error("failed to encrypt server config")]
+    Encrypt(chacha20poly1305::Error),
+
+    #[error("failed to decrypt server config")]
+    Decrypt(chacha20poly1305::Error),
+
+    #[error("failed to serialize server config: {0}")]
+    Serialize(serde_json::Error),
+
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// Persisted vault parameters
+#[derive(Serialize, Deserialize)]
+struct VaultSettings {
+    salt: Vec<u8>,
+    /// Key derivation parameters, vaults created before these were recorded
+    /// use the legacy parameters
+    #[serde(default = "KdfParams::legacy")]
+    kdf: KdfParams,
+    /// [VAULT_VERIFIER] encrypted with the vault key
+    verifier_nonce: Vec<u8>,
+    verifier: Vec<u8>,
+}
+
+/// Unlocked state of the vault for the current session
+#[derive(Default)]
+pub struct Vault {
+    key: Mutex<Option<DerivedKey>>,
+}
+
+impl Vault {
+    /// Check if the vault has been enabled
+    pub async fn is_enabled(db: impl DbExecutor<'_>) -> Result<bool, VaultError> {
+        Ok(Setting::get::<VaultSettings>(db, VAULT_SETTING)
+            .await?
+            .is_some())
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.key
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .is_some()
+    }
+
+    /// Enable the vault using `password` as the master password, the vault
+    /// is unlocked afterwards
+    pub async fn create(
+        &self,
+        db: impl DbExecutor<'_> + Copy,
+        password: &str,
+    ) -> Result<(), VaultError> {
+        if Self::is_enabled(db).await? {
+            return Err(VaultError::AlreadyEnabled);
+        }
+
+        let (key, settings) = new_vault_key(password)?;
+        Setting::set(db, VAULT_SETTING, &settings).await?;
+
+        self.set_key(Some(key));
+        Ok(())
+    }
+
+    /// Unlock the vault for the session using the master password. Vaults
+    /// using outdated key derivation parameters are re-keyed
+    pub async fn unlock(&self, db: &DbPool, password: &str) -> Result<(), VaultError> {
+        let settings: VaultSettings = Setting::get(db, VAULT_SETTING)
+            .await?
+            .ok_or(VaultError::NotEnabled)?;
+
+        let key = derive_key(password.as_bytes(), None, &settings.salt, &settings.kdf)
+            .map_err(VaultError::DeriveKey)?;
+
+        match decrypt_with_key(&key, &settings.verifier_nonce, &settings.verifier) {
+            Ok(verifier) if verifier == VAULT_VERIFIER => {}
+            _ => return Err(VaultError::IncorrectPassword),
+        }
+
+        // A failed upgrade leaves the vault as it was, it is still unlocked
+        // using the current key and the upgrade is tried again next time
+        let key = if settings.kdf != KdfParams::current() {
+            match rekey_vault(db, &key, password).await {
+                Ok(new_key) => new_key,
+                Err(error) => {
+                    tracing::warn!(?error, "failed to upgrade vault key");
+                    key
+                }
+            }
+        } else {
+            key
+        };
+
+        self.set_key(Some(key));
+        Ok(())
+    }
+
+    /// Lock the vault, removing the key from memory
+    pub fn lock(&self) {
+        self.set_key(None);
+    }
+
+    /// Disable the vault, servers must be moved out of the vault first
+    pub async fn disable(&self, db: impl DbExecutor<'_>) -> Result<(), VaultError> {
+        Setting::remove(db, VAULT_SETTING).await?;
+        self.lock();
+        Ok(())
+    }
+
+    /// Encrypt server config data into a vault config
+    pub fn seal(&self, data: &ServerConfigData) -> Result<ServerConfig, VaultError> {
+        let key = self.key.lock().unwrap_or_else(|err| err.into_inner());
+        let key = key.as_ref().ok_or(VaultError::Locked)?;
+
+        let data = Zeroizing::new(serde_json::to_vec(data).map_err(VaultError::Serialize)?);
+        let (nonce, data) = encrypt_with_key(key, &data).map_err(VaultError::Encrypt)?;
+
+        Ok(ServerConfig::Vault { nonce, data })
+    }
+
+    /// Decrypt the config data of a vault config
+    pub fn open(&self, nonce: &[u8], data: &[u8]) -> Result<ServerConfigData, VaultError> {
+        let key = self.key.lock().unwrap_or_else(|err| err.into_inner());
+        let key = key.as_ref().ok_or(VaultError::Locked)?;
+
+        let data = Zeroizing::new(decrypt_with_key(key, nonce, data).map_err(VaultError::Decrypt)?);
+        serde_json::from_slice(&data).map_err(VaultError::Serialize)
+    }
+
+    fn set_key(&self, key: Option<DerivedKey>) {
+        *self.key.lock().unwrap_or_else(|err| err.into_inner()) = key;
+    }
+}
+
+/// Derive a key from `password` using a new salt and the current key
+/// derivation parameters, returns the key and the settings to persist
+fn new_vault_key(password: &str) -> Result<(DerivedKey, VaultSettings), VaultError> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    let kdf = KdfParams::current();
+    let key = derive_key(password.as_bytes(), None, &salt, &kdf).map_err(VaultError::DeriveKey)?;
+    let (verifier_nonce, verifier) =
+        encrypt_with_key(&key, VAULT_VERIFIER).map_err(VaultError::Encrypt)?;
+
+    Ok((
+        key,
+        VaultSettings {
+            salt: salt.to_vec(),
+            kdf,
+            verifier_nonce,
+            verifier,
+        },
+    ))
+}
+
+/// Replace the vault key with one derived using the current key derivation
+/// parameters, re-encrypting every vault config with the new key
+async fn rekey_vault(
+    db: &DbPool,
+    key: &DerivedKey,
+    password: &str,
+) -> Result<DerivedKey, VaultError> {
+    let (new_key, settings) = new_vault_key(password)?;
+    let mut tx = db.begin().await?;
+
+    for server in Server::all(&mut *tx).await? {
+        let ServerConfig::Vault { nonce, data } = &server.config else {
+            continue;
+        };
+
+        let data = Zeroizing::new(decrypt_with_key(key, nonce, data).map_err(VaultError::Decrypt)?);
+        let (nonce, data) = encrypt_with_key(&new_key, &data).map_err(VaultError::Encrypt)?;
+
+        let update = UpdateServer {
+            name: None,
+            config: Some(ServerConfig::Vault { nonce, data }),
+        };
+
+        Server::update(&mut *tx, server.id, &update).await?;
+    }
+
+    Setting::set(&mut *tx, VAULT_SETTING, &settings).await?;
+    tx.commit().await?;
+
+    Ok(new_key)
+}
@@ -1,22 +1,191 @@
-use argon2::{password_hash::rand_core::RngCore, Argon2};
+use argon2::{password_hash::rand_core::RngCore, Argon2, Params, Version};
 use chacha20poly1305::{
     aead::{Aead, KeyInit, OsRng},
     AeadCore, Key, XChaCha20Poly1305, XNonce,
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use zeroize::Zeroizing;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Version of the encryption envelope produced by [encrypt]
+pub const ENVELOPE_VERSION: u32 = 1;
+
+/// Length of derived keys in bytes
+const KEY_LENGTH: usize = 32;
+
+/// Length of XChaCha20Poly1305 nonces in bytes
+const NONCE_LENGTH: usize = 24;
+
+/// Shortest salt accepted by Argon2 in bytes
+const MIN_SALT_LENGTH: usize = 8;
+
+/// Longest salt accepted from encrypted content in bytes
+const MAX_SALT_LENGTH: usize = 64;
+
+/// Largest Argon2 memory cost accepted from encrypted content, in KiB
+const MAX_MEMORY_COST: u32 = 256 * 1024;
+
+/// Largest Argon2 time cost accepted from encrypted content
+const MAX_TIME_COST: u32 = 16;
+
+/// Largest Argon2 parallelism accepted from encrypted content
+const MAX_PARALLELISM: u32 = 16;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EncryptedContent {
     pub salt: Vec<u8>,
     pub nonce: Vec<u8>,
     pub data: Vec<u8>,
+    /// Parameters the content was encrypted with, content from before the
+    /// parameters were recorded uses the legacy parameters
+    #[serde(default = "EncryptionParams::legacy")]
+    pub params: EncryptionParams,
+    /// Whether a key file is required in addition to the password
+    #[serde(default)]
+    pub key_file: bool,
+}
+
+impl EncryptedContent {
+    /// Whether the content was encrypted with outdated parameters and
+    /// should be re-encrypted
+    pub fn needs_upgrade(&self) -> bool {
+        self.params != EncryptionParams::current()
+    }
+
+    /// Check the envelope could have been produced by [encrypt], content
+    /// from outside the app should be checked before attempting to decrypt
+    pub fn is_well_formed(&self) -> bool {
+        (MIN_SALT_LENGTH..=MAX_SALT_LENGTH).contains(&self.salt.len())
+            && self.nonce.len() == NONCE_LENGTH
+            && self.params.kdf.is_within_limits()
+    }
+}
+
+/// Algorithms and costs used to produce encrypted content
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptionParams {
+    /// Envelope version, 0 for content from before it was recorded
+    pub version: u32,
+    pub cipher: Cipher,
+    pub kdf: KdfParams,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cipher {
+    XChaCha20Poly1305,
+}
+
+/// Argon2 key derivation parameters
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub variant: Argon2Variant,
+    /// Memory cost in KiB
+    pub memory_cost: u32,
+    /// Number of iterations
+    pub time_cost: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Argon2Variant {
+    Argon2d,
+    Argon2i,
+    Argon2id,
+}
+
+impl EncryptionParams {
+    /// Parameters used for new content
+    pub fn current() -> Self {
+        Self {
+            version: ENVELOPE_VERSION,
+            cipher: Cipher::XChaCha20Poly1305,
+            kdf: KdfParams::current(),
+        }
+    }
+
+    /// Parameters of content encrypted before they were recorded
+    pub fn legacy() -> Self {
+        Self {
+            version: 0,
+            cipher: Cipher::XChaCha20Poly1305,
+            kdf: KdfParams::legacy(),
+        }
+    }
+}
+
+impl KdfParams {
+    /// Key derivation parameters used for new content
+    pub fn current() -> Self {
+        Self {
+            variant: Argon2Variant::Argon2id,
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    /// Argon2 defaults at the time the parameters started being recorded
+    pub fn legacy() -> Self {
+        Self {
+            variant: Argon2Variant::Argon2id,
+            memory_cost: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+        }
+    }
+
+    /// Check the costs are within the accepted maximums, content can come
+    /// from outside the app such as bundle files so the costs recorded in it
+    /// cannot be trusted
+    fn is_within_limits(&self) -> bool {
+        self.memory_cost <= MAX_MEMORY_COST
+            && self.time_cost <= MAX_TIME_COST
+            && self.parallelism <= MAX_PARALLELISM
+    }
+
+    fn argon2<'key>(&self, secret: Option<&'key [u8]>) -> Result<Argon2<'key>, DeriveKeyError> {
+        if !self.is_within_limits() {
+            return Err(DeriveKeyError::ExcessiveParams);
+        }
+
+        let algorithm = match self.variant {
+            Argon2Variant::Argon2d => argon2::Algorithm::Argon2d,
+            Argon2Variant::Argon2i => argon2::Algorithm::Argon2i,
+            Argon2Variant::Argon2id => argon2::Algorithm::Argon2id,
+        };
+
+        let params = Params::new(
+            self.memory_cost,
+            self.time_cost,
+            self.parallelism,
+            Some(KEY_LENGTH),
+        )
+        .map_err(DeriveKeyError::Argon2)?;
+
+        match secret {
+            Some(secret) => Argon2::new_with_secret(secret, algorithm, Version::V0x13, params)
+                .map_err(DeriveKeyError::Argon2),
+            None => Ok(Argon2::new(algorithm, Version::V0x13, params)),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DeriveKeyError {
+    #[error("key derivation parameters exceed the allowed maximums")]
+    ExcessiveParams,
+
+    #[error("failed to derive key: {0}")]
+    Argon2(argon2::Error),
 }
 
 #[derive(Debug, Error)]
 pub enum EncryptError {
     #[error("failed to hash password")]
-    HashPassword(argon2::Error),
+    HashPassword(DeriveKeyError),
 
     #[error("failed to encrypt data")]
     Encrypt(chacha20poly1305::Error),
@@ -25,14 +194,74 @@ pub enum EncryptError {
 #[derive(Debug, Error)]
 pub enum DecryptError {
     #[error("failed to hash password")]
-    HashPassword(argon2::Error),
+    HashPassword(DeriveKeyError),
 
     #[error("failed to encrypt data")]
     Decrypt(chacha20poly1305::Error),
+
+    #[error("key file is required")]
+    MissingKeyFile,
+}
+
+/// Encryption key derived from a password
+pub type DerivedKey = Zeroizing<[u8; KEY_LENGTH]>;
+
+/// Derive an encryption key from a password and salt, the contents of a
+/// key file are mixed in as the Argon2 secret when provided
+pub fn derive_key(
+    password: &[u8],
+    key_file: Option<&[u8]>,
+    salt: &[u8],
+    params: &KdfParams,
+) -> Result<DerivedKey, DeriveKeyError> {
+    let mut key_bytes = Zeroizing::new([0u8; KEY_LENGTH]);
+    params
+        .argon2(key_file)?
+        .hash_password_into(password, salt, &mut key_bytes[..])
+        .map_err(DeriveKeyError::Argon2)?;
+    Ok(key_bytes)
+}
+
+/// Encrypts some data using a derived key, returns the nonce and the
+/// encrypted data
+pub fn encrypt_with_key(
+    key: &DerivedKey,
+    input: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), chacha20poly1305::Error> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&key[..]));
+
+    // Generate nonce
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    // Encrypt content
+    let data = cipher.encrypt(&nonce, input)?;
+
+    Ok((nonce.to_vec(), data))
+}
+
+/// Decrypt some data using a derived key and the nonce it was encrypted with
+pub fn decrypt_with_key(
+    key: &DerivedKey,
+    nonce: &[u8],
+    input: &[u8],
+) -> Result<Vec<u8>, chacha20poly1305::Error> {
+    // Nonces of the wrong length cannot have come from [encrypt_with_key]
+    if nonce.len() != NONCE_LENGTH {
+        return Err(chacha20poly1305::Error);
+    }
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&key[..]));
+    let nonce = XNonce::from_slice(nonce);
+
+    cipher.decrypt(nonce, input)
 }
 
-/// Encrypts some data using a password
-pub fn encrypt(password: &[u8], input: &[u8]) -> Result<EncryptedContent, EncryptError> {
+/// Encrypts some data using a password and optionally a key file
+pub fn encrypt(
+    password: &[u8],
+    key_file: Option<&[u8]>,
+    input: &[u8],
+) -> Result<EncryptedContent, EncryptError> {
     let mut rng = OsRng;
 
     // Generate a random salt
@@ -40,51 +269,186 @@ pub fn encrypt(password: &[u8], input: &[u8]) -> Result<EncryptedContent, Encryp
     rng.fill_bytes(&mut salt);
 
     // Derive key from password + salt
-    let mut key_bytes = [0u8; 32];
-    Argon2::default()
-        .hash_password_into(password, &salt, &mut key_bytes)
-        .map_err(EncryptError::HashPassword)?;
-
-    let key = Key::from_slice(&key_bytes);
-
-    // Create cipher
-    let cipher = XChaCha20Poly1305::new(key);
-
-    // Generate nonce
-    let nonce = XChaCha20Poly1305::generate_nonce(&mut rng);
+    let params = EncryptionParams::current();
+    let key =
+        derive_key(password, key_file, &salt, &params.kdf).map_err(EncryptError::HashPassword)?;
 
     // Encrypt content
-    let data = cipher
-        .encrypt(&nonce, input)
-        .map_err(EncryptError::Encrypt)?;
+    let (nonce, data) = encrypt_with_key(&key, input).map_err(EncryptError::Encrypt)?;
 
     Ok(EncryptedContent {
         salt: salt.to_vec(),
-        nonce: nonce.to_vec(),
+        nonce,
         data,
+        params,
+        key_file: key_file.is_some(),
     })
 }
 
-/// Decrypt a secret using its parts
+/// Decrypt content using the parameters it was encrypted with, `key_file`
+/// is ignored when the content does not require a key file
 pub fn decrypt(
     password: &[u8],
-    salt: &[u8],
-    nonce: &[u8],
-    input: &[u8],
+    key_file: Option<&[u8]>,
+    content: &EncryptedContent,
 ) -> Result<Vec<u8>, DecryptError> {
-    // Derive the key from the password and salt
-    let mut key_bytes = [0u8; 32];
-    Argon2::default()
-        .hash_password_into(password, salt, &mut key_bytes)
+    let key_file = match (content.key_file, key_file) {
+        (true, Some(key_file)) => Some(key_file),
+        (true, None) => return Err(DecryptError::MissingKeyFile),
+        (false, _) => None,
+    };
+
+    // Derive the key from the password, key file and salt
+    let key = derive_key(password, key_file, &content.salt, &content.params.kdf)
         .map_err(DecryptError::HashPassword)?;
 
-    let key = Key::from_slice(&key_bytes);
+    // Attempt decryption, XChaCha20Poly1305 is currently the only cipher
+    match content.params.cipher {
+        Cipher::XChaCha20Poly1305 => {
+            decrypt_with_key(&key, &content.nonce, &content.data).map_err(DecryptError::Decrypt)
+        }
+    }
+}
 
-    // Create cipher
-    let cipher = XChaCha20Poly1305::new(key);
+/// Re-encrypt content that was encrypted with outdated parameters, returns
+/// [None] when the content is already up to date. `plaintext` must be the
+/// decrypted content. The key file requirement is kept, content requiring a
+/// key file is left as is when `key_file` is not provided
+pub fn upgrade(
+    password: &[u8],
+    key_file: Option<&[u8]>,
+    content: &EncryptedContent,
+    plaintext: &[u8],
+) -> Result<Option<EncryptedContent>, EncryptError> {
+    if !content.needs_upgrade() {
+        return Ok(None);
+    }
 
-    // Attempt decryption
-    let nonce = XNonce::from_slice(nonce);
+    let key_file = match (content.key_file, key_file) {
+        (true, Some(key_file)) => Some(key_file),
+        (true, None) => return Ok(None),
+        (false, _) => None,
+    };
+
+    encrypt(password, key_file, plaintext).map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Encrypt `input` the way content was encrypted before the parameters
+    /// and key file requirement were recorded
+    fn legacy_content(password: &[u8], input: &[u8]) -> EncryptedContent {
+        let salt = [7u8; 16];
+        let key = derive_key(password, None, &salt, &KdfParams::legacy()).unwrap();
+        let (nonce, data) = encrypt_with_key(&key, input).unwrap();
+
+        // Legacy content only has the salt, nonce and data fields
+        let json = serde_json::json!({
+            "salt": salt.to_vec(),
+            "nonce": nonce,
+            "data": data,
+        });
+
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_decrypt_legacy_content() {
+        let content = legacy_content(b"password", b"secret");
+
+        assert_eq!(content.params, EncryptionParams::legacy());
+        assert!(!content.key_file);
+        assert!(content.needs_upgrade());
+        assert_eq!(decrypt(b"password", None, &content).unwrap(), b"secret");
+        assert!(decrypt(b"incorrect", None, &content).is_err());
+    }
+
+    #[test]
+    fn test_upgrade_legacy_content() {
+        let content = legacy_content(b"password", b"secret");
+
+        let upgraded = upgrade(b"password", None, &content, b"secret")
+            .unwrap()
+            .expect("legacy content should be upgraded");
+
+        assert_eq!(upgraded.params, EncryptionParams::current());
+        assert!(!upgraded.needs_upgrade());
+        assert_eq!(decrypt(b"password", None, &upgraded).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_upgrade_current_content() {
+        let content = encrypt(b"password", None, b"secret").unwrap();
+
+        assert!(upgrade(b"password", None, &content, b"secret")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_upgrade_keeps_key_file() {
+        let mut content = encrypt(b"password", Some(b"key file"), b"secret").unwrap();
+        content.params = EncryptionParams::legacy();
+
+        // Content requiring a key file is not upgraded without the key file
+        assert!(upgrade(b"password", None, &content, b"secret")
+            .unwrap()
+            .is_none());
+
+        let upgraded = upgrade(b"password", Some(b"key file"), &content, b"secret")
+            .unwrap()
+            .expect("content should be upgraded");
+
+        assert!(upgraded.key_file);
+        assert!(matches!(
+            decrypt(b"password", None, &upgraded),
+            Err(DecryptError::MissingKeyFile)
+        ));
+        assert_eq!(
+            decrypt(b"password", Some(b"key file"), &upgraded).unwrap(),
+            b"secret"
+        );
+    }
+
+    #[test]
+    fn test_decrypt_rejects_excessive_params() {
+        let mut content = encrypt(b"password", None, b"secret").unwrap();
+        content.params.kdf.memory_cost = u32::MAX;
+
+        assert!(matches!(
+            decrypt(b"password", None, &content),
+            Err(DecryptError::HashPassword(DeriveKeyError::ExcessiveParams))
+        ));
+    }
+
+    #[test]
+    fn test_is_well_formed() {
+        let content = encrypt(b"password", None, b"secret").unwrap();
+        assert!(content.is_well_formed());
+
+        let mut invalid = content.clone();
+        invalid.nonce.truncate(4);
+        assert!(!invalid.is_well_formed());
+
+        let mut invalid = content.clone();
+        invalid.salt.clear();
+        assert!(!invalid.is_well_formed());
+
+        let mut invalid = content;
+        invalid.params.kdf.time_cost = u32::MAX;
+        assert!(!invalid.is_well_formed());
+    }
+
+    #[test]
+    fn test_decrypt_rejects_invalid_nonce() {
+        let mut content = encrypt(b"password", None, b"secret").unwrap();
+        content.nonce.truncate(4);
 
-    cipher.decrypt(nonce, input).map_err(DecryptError::Decrypt)
+        assert!(matches!(
+            decrypt(b"password", None, &content),
+            Err(DecryptError::Decrypt(_))
+        ));
+    }
 }
@@ -0,0 +1,55 @@
+//! Key files used as a second factor alongside a password, the contents of
+//! the key file are used as the Argon2 secret when deriving the key
+
+use std::{io, path::Path};
+
+use argon2::password_hash::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use tokio::io::AsyncWriteExt;
+use zeroize::Zeroizing;
+
+/// Number of random bytes in a generated key file
+const KEY_FILE_LENGTH: usize = 64;
+
+/// Largest key file that will be read
+const MAX_KEY_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Contents of a key file
+pub type KeyFile = Zeroizing<Vec<u8>>;
+
+/// Generate a key file of random bytes at `path`, an existing file is never
+/// overwritten as anything encrypted with it would become unrecoverable
+pub async fn generate_key_file(path: &Path) -> io::Result<()> {
+    let mut key = Zeroizing::new(vec![0u8; KEY_FILE_LENGTH]);
+    OsRng.fill_bytes(&mut key);
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+
+    file.write_all(&key).await?;
+    file.sync_all().await
+}
+
+/// Read the contents of a key file
+pub async fn read_key_file(path: &Path) -> io::Result<KeyFile> {
+    let metadata = tokio::fs::metadata(path).await?;
+    if metadata.len() > MAX_KEY_FILE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "key file is too large",
+        ));
+    }
+
+    let data = Zeroizing::new(tokio::fs::read(path).await?);
+    if data.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "key file is empty",
+        ));
+    }
+
+    Ok(data)
+}
@@ -1 +1,3 @@
 pub mod encryption;
+pub mod key_file;
+pub mod secret;
@@ -0,0 +1,5 @@
+use zeroize::Zeroizing;
+
+/// String holding sensitive data such as a password or key, the contents
+/// are wiped from memory when dropped
+pub type SecretString = Zeroizing<String>;
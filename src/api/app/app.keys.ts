@@ -0,0 +1,5 @@
+export const appKeys = {
+  migrations: ["app", "migrations"],
+  backups: ["app", "backups"],
+  restoreBackup: ["app", "backups", "restore"],
+};
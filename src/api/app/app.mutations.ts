@@ -0,0 +1,10 @@
+import { useMutation } from "@tanstack/react-query";
+import { appKeys } from "./app.keys";
+import { restoreBackup } from "./app.requests";
+
+export function useRestoreBackup() {
+  return useMutation({
+    mutationKey: appKeys.restoreBackup,
+    mutationFn: (name: string) => restoreBackup(name),
+  });
+}
@@ -0,0 +1,19 @@
+import { useQuery } from "@tanstack/react-query";
+import { appKeys } from "./app.keys";
+import { checkMigrations, getBackups } from "./app.requests";
+
+export function useMigrationCheck() {
+  return useQuery({
+    queryKey: appKeys.migrations,
+    queryFn: () => checkMigrations().then(() => null),
+    retry: false,
+  });
+}
+
+export function useBackups(enabled: boolean) {
+  return useQuery({
+    queryKey: appKeys.backups,
+    queryFn: () => getBackups(),
+    enabled,
+  });
+}
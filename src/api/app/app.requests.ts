@@ -0,0 +1,23 @@
+import type { Backup } from "./app.types";
+
+import { invoke } from "@tauri-apps/api/core";
+
+export function checkMigrations() {
+  return invoke<void>("app_check_migrations");
+}
+
+export function getBackups() {
+  return invoke<Backup[]>("app_list_backups");
+}
+
+export function restoreBackup(name: string) {
+  return invoke<void>("app_restore_backup", { name });
+}
+
+export function rollbackMigration(name: string) {
+  return invoke<void>("app_rollback_migration", { name });
+}
+
+export function reportActivity() {
+  return invoke<void>("app_report_activity");
+}
@@ -0,0 +1,7 @@
+export interface Backup {
+  /** File name of the backup */
+  name: string;
+  created_at: string;
+  /** Size of the backup in bytes */
+  size: number;
+}
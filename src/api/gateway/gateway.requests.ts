@@ -0,0 +1,10 @@
+import { invoke } from "@tauri-apps/api/core";
+
+/**
+ * Header identifying a gateway request, used to cancel the request
+ */
+export const GATEWAY_REQUEST_ID_HEADER = "x-gateway-request-id";
+
+export function cancelGatewayRequest(requestId: string) {
+  return invoke<boolean>("gateway_cancel", { requestId });
+}
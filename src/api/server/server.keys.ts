@@ -4,4 +4,6 @@ export const serverKeys = {
   server: (serverId: string) => ["server", serverId],
   closeServer: (serverId: string) => ["server", serverId, "close"],
   removeServer: (serverId: string) => ["server", serverId, "remove"],
+  setReadOnly: (serverId: string) => ["server", serverId, "read-only"],
+  insecure: (serverId: string) => ["server", serverId, "insecure"],
 };
@@ -1,6 +1,11 @@
 import { useMutation } from "@tanstack/react-query";
 import { serverKeys } from "./server.keys";
-import { createServer, deleteServer, loadServer } from "./server.requests";
+import {
+  createServer,
+  deleteServer,
+  loadServer,
+  setServerReadOnly,
+} from "./server.requests";
 import { CreateServer, LoadServerConfig } from "./server.types";
 import { queryClient } from "@/integrations/tanstack-query/root-provider";
 
@@ -24,6 +29,11 @@ export function useLoadServer() {
       serverId: string;
       loadConfig: LoadServerConfig;
     }) => loadServer(serverId, loadConfig),
+    onSuccess(_, { serverId }) {
+      queryClient.invalidateQueries({
+        queryKey: serverKeys.insecure(serverId),
+      });
+    },
   });
 }
 
@@ -36,3 +46,13 @@ export function useDeleteServer(serverId: string) {
     },
   });
 }
+
+export function useSetServerReadOnly(serverId: string) {
+  return useMutation({
+    mutationKey: serverKeys.setReadOnly(serverId),
+    mutationFn: (readOnly: boolean) => setServerReadOnly(serverId, readOnly),
+    onSuccess() {
+      queryClient.invalidateQueries({ queryKey: serverKeys.servers });
+    },
+  });
+}
@@ -1,6 +1,6 @@
 import { useQuery } from "@tanstack/react-query";
 import { serverKeys } from "./server.keys";
-import { getServers } from "./server.requests";
+import { getServers, isServerInsecure } from "./server.requests";
 
 export function useServers() {
   return useQuery({
@@ -8,3 +8,15 @@ export function useServers() {
     queryFn: getServers,
   });
 }
+
+/**
+ * Whether a server has TLS certificate verification disabled, only known
+ * once the server is loaded so unloaded servers report false
+ */
+export function useServerInsecure(serverId: string | undefined) {
+  return useQuery({
+    queryKey: serverKeys.insecure(serverId ?? ""),
+    queryFn: () => isServerInsecure(serverId!).catch(() => false),
+    enabled: !!serverId,
+  });
+}
@@ -16,3 +16,14 @@ export function loadServer(serverId: string, loadConfig: LoadServerConfig) {
 export function deleteServer(serverId: string) {
   return invoke("server_delete", { serverId });
 }
+
+export function setServerReadOnly(serverId: string, readOnly: boolean) {
+  return invoke("server_set_read_only", { serverId, readOnly });
+}
+
+/**
+ * Check if a loaded server has TLS certificate verification disabled
+ */
+export function isServerInsecure(serverId: string) {
+  return invoke<boolean>("server_is_insecure", { serverId });
+}
@@ -1,9 +1,12 @@
 import { ServerConfigData } from "../server";
+import { EncryptedContent } from "../utils/utils.types";
 
 export interface Server {
   id: string;
   name: string;
   order: number;
+  /** Whether the server is restricted to read only access */
+  read_only: boolean;
 }
 
 export interface CreateServer {
@@ -11,6 +14,7 @@ export interface CreateServer {
   name: string;
   config: ServerConfig;
   order: number;
+  read_only?: boolean;
 }
 
 export enum ServerConfigType {
@@ -32,12 +36,11 @@ export interface ServerConfigConfig {
   data: ServerConfigData;
 }
 
-export interface ServerConfigEncrypted {
-  salt: number[];
-  nonce: number[];
-  data: number[];
-}
+/** Encrypted configs must be stored exactly as returned by `encrypt` */
+export type ServerConfigEncrypted = EncryptedContent;
 
 export interface LoadServerConfig {
   password?: string | null;
+  /** Path to the key file for configs that require one */
+  key_file?: string | null;
 }
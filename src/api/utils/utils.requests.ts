@@ -3,7 +3,8 @@ import { EncryptedContent } from "./utils.types";
 
 export function encrypt(
   password: string,
-  input: string
+  input: string,
+  keyFile?: string | null
 ): Promise<EncryptedContent> {
-  return invoke("utils_encrypt", { password, input });
+  return invoke("utils_encrypt", { password, input, keyFile });
 }
@@ -2,4 +2,21 @@ export interface EncryptedContent {
   salt: number[];
   nonce: number[];
   data: number[];
+  /** Parameters the content was encrypted with, absent on legacy content */
+  params?: EncryptionParams;
+  /** Whether a key file is required in addition to the password */
+  key_file?: boolean;
+}
+
+export interface EncryptionParams {
+  version: number;
+  cipher: "x_cha_cha20_poly1305";
+  kdf: KdfParams;
+}
+
+export interface KdfParams {
+  variant: "argon2d" | "argon2i" | "argon2id";
+  memory_cost: number;
+  time_cost: number;
+  parallelism: number;
 }
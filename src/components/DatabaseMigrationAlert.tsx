@@ -0,0 +1,59 @@
+import { getAPIErrorMessage } from "@/api/axios";
+import { useRestoreBackup } from "@/api/app/app.mutations";
+import { useBackups, useMigrationCheck } from "@/api/app/app.queries";
+import Alert from "@mui/material/Alert";
+import AlertTitle from "@mui/material/AlertTitle";
+import Button from "@mui/material/Button";
+import Typography from "@mui/material/Typography";
+import { Stack } from "@mui/system";
+
+/**
+ * Reports a failure to migrate the app database at startup and offers to
+ * restore one of the backups taken before migrating
+ */
+export default function DatabaseMigrationAlert() {
+  const { error } = useMigrationCheck();
+  const backups = useBackups(!!error);
+  const restore = useRestoreBackup();
+
+  if (!error) return null;
+
+  return (
+    <Alert color="error" sx={{ m: 3 }}>
+      <AlertTitle>Failed to migrate the app database</AlertTitle>
+      <Typography variant="body2">{getAPIErrorMessage(error)}</Typography>
+
+      <Stack spacing={1} sx={{ mt: 2 }}>
+        {backups.data?.map((backup) => (
+          <Stack
+            key={backup.name}
+            direction="row"
+            alignItems="center"
+            justifyContent="space-between"
+          >
+            <Typography variant="body2">
+              {new Date(backup.created_at).toLocaleString()}
+            </Typography>
+            <Button
+              size="small"
+              disabled={restore.isPending}
+              onClick={() => restore.mutate(backup.name)}
+            >
+              Restore
+            </Button>
+          </Stack>
+        ))}
+
+        {backups.data?.length === 0 && (
+          <Typography variant="body2">No backups available</Typography>
+        )}
+
+        {restore.error && (
+          <Typography variant="body2">
+            Failed to restore backup: {getAPIErrorMessage(restore.error)}
+          </Typography>
+        )}
+      </Stack>
+    </Alert>
+  );
+}
@@ -4,8 +4,12 @@ import Toolbar from "@mui/material/Toolbar";
 import Typography from "@mui/material/Typography";
 import RouterLink from "./RouterLink";
 import Button from "@mui/material/Button";
+import { useParams } from "@tanstack/react-router";
+import InsecureServerChip from "./server/InsecureServerChip";
 
 export default function Header() {
+  const { serverId } = useParams({ strict: false });
+
   return (
     <AppBar position="sticky">
       <Toolbar>
@@ -26,6 +30,8 @@ export default function Header() {
           </Typography>
         </RouterLink>
 
+        <InsecureServerChip serverId={serverId} />
+
         <Box sx={{ display: "flex" }}>
           <Button component={RouterLink} to="/" sx={{ my: 1, mr: 1 }}>
             Servers
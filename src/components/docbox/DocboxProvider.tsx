@@ -3,20 +3,32 @@ import axios from "axios";
 import {
   createContext,
   useContext,
+  useEffect,
   useMemo,
   type PropsWithChildren,
 } from "react";
 import { platform } from "@tauri-apps/plugin-os";
+import {
+  cancelGatewayRequest,
+  GATEWAY_REQUEST_ID_HEADER,
+} from "@/api/gateway/gateway.requests";
 
 interface DocboxContextType {
   baseURL: string;
   client: DocboxClient;
+  /** Cancel all of the requests still in flight through the gateway */
+  cancelAll: () => void;
 }
 
 export type DocboxClientExt = DocboxClient & {
   __unique_tenant_key: string;
 };
 
+/**
+ * Header exposing the URL of a streamed response
+ */
+const STREAM_URL_HEADER = "x-docbox-stream-url";
+
 const DocboxContext = createContext<DocboxContextType>(null!);
 
 export function useDocboxClient() {
@@ -62,14 +74,83 @@ export default function DocboxProvider({
       },
     });
 
+    // Requests still waiting on the gateway, cancelled when the provider
+    // unmounts or the webview unloads. Only requests made through this
+    // client can be cancelled, URLs loaded directly by the webview (PDF.js,
+    // media elements) run until complete unless served by the stream server
+    const inFlight = new Set<string>();
+
+    const cancelRequest = (requestId: string) => {
+      if (!inFlight.delete(requestId)) return;
+      cancelGatewayRequest(requestId).catch((error) => {
+        console.error("failed to cancel gateway request", error);
+      });
+    };
+
+    axiosInstance.interceptors.request.use((config) => {
+      const requestId = crypto.randomUUID();
+      config.headers.set(GATEWAY_REQUEST_ID_HEADER, requestId);
+      inFlight.add(requestId);
+
+      const signal = config.signal as AbortSignal | undefined;
+      signal?.addEventListener("abort", () => cancelRequest(requestId), {
+        once: true,
+      });
+
+      return config;
+    });
+
+    const completeRequest = (config?: { headers?: any }) => {
+      const requestId = config?.headers?.get?.(GATEWAY_REQUEST_ID_HEADER);
+      if (typeof requestId === "string") inFlight.delete(requestId);
+    };
+
+    axiosInstance.interceptors.response.use(
+      (response) => {
+        completeRequest(response.config);
+        return response;
+      },
+      (error) => {
+        completeRequest(error?.config);
+
+        // Large responses are redirected to the stream server, the URL is
+        // also provided as a header for webviews that do not follow the
+        // redirect from the custom protocol
+        const streamURL = error?.response?.headers?.[STREAM_URL_HEADER];
+        if (error?.response?.status === 307 && typeof streamURL === "string") {
+          return axios.request({ ...error.config, url: streamURL });
+        }
+
+        return Promise.reject(error);
+      }
+    );
+
+    const cancelAll = () => {
+      for (const requestId of Array.from(inFlight)) {
+        cancelRequest(requestId);
+      }
+    };
+
     const docbox = new DocboxClient(axiosInstance);
     const client = Object.assign(docbox, {
       __unique_tenant_key: `${env}-${tenantId}`,
     });
 
-    return { baseURL, client };
+    return { baseURL, client, cancelAll };
   }, [env, tenantId]);
 
+  // Cancel outstanding requests when navigating away from the tenant or
+  // when the webview is unloaded
+  useEffect(() => {
+    const { cancelAll } = value;
+    window.addEventListener("beforeunload", cancelAll);
+
+    return () => {
+      window.removeEventListener("beforeunload", cancelAll);
+      cancelAll();
+    };
+  }, [value]);
+
   return (
     <DocboxContext.Provider value={value}>{children}</DocboxContext.Provider>
   );
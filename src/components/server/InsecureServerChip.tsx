@@ -0,0 +1,27 @@
+import { useServerInsecure } from "@/api/server/server.queries";
+import Chip from "@mui/material/Chip";
+import Tooltip from "@mui/material/Tooltip";
+
+type Props = {
+  serverId: string | undefined;
+};
+
+/**
+ * Warning shown for loaded servers that accept invalid TLS certificates
+ */
+export default function InsecureServerChip({ serverId }: Props) {
+  const { data: insecure } = useServerInsecure(serverId);
+
+  if (!insecure) return null;
+
+  return (
+    <Tooltip title="TLS certificate verification is disabled for this server, connections can be intercepted">
+      <Chip
+        size="small"
+        color="warning"
+        label="Insecure TLS"
+        sx={{ ml: 1 }}
+      />
+    </Tooltip>
+  );
+}
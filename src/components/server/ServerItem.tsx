@@ -8,18 +8,31 @@ import SolarTrashBin2Bold from "~icons/solar/trash-bin-2-bold";
 
 import SolarServer2BoldDuotone from "~icons/solar/server-2-bold-duotone";
 import ListItemIcon from "@mui/material/ListItemIcon";
+import Chip from "@mui/material/Chip";
+import FormControlLabel from "@mui/material/FormControlLabel";
+import Switch from "@mui/material/Switch";
 import { useState } from "react";
+import { useSetServerReadOnly } from "@/api/server/server.mutations";
 import { ConfirmDeleteServerItem } from "./ConfirmDeleteServerItem";
+import InsecureServerChip from "./InsecureServerChip";
 
 type Props = {
   serverId: string;
   name: string;
+  readOnly: boolean;
 
   onLoad: VoidFunction;
 };
 
-export default function ServerSelectItem({ serverId, name, onLoad }: Props) {
+export default function ServerSelectItem({
+  serverId,
+  name,
+  readOnly,
+  onLoad,
+}: Props) {
   const [confirmDelete, setConfirmDelete] = useState(false);
+  const { mutate: setReadOnly, isPending: isSettingReadOnly } =
+    useSetServerReadOnly(serverId);
 
   return (
     <ListItem>
@@ -30,9 +43,29 @@ export default function ServerSelectItem({ serverId, name, onLoad }: Props) {
         />
       </ListItemIcon>
 
-      <ListItemText primary={name} secondary={serverId} />
+      <ListItemText
+        primary={
+          <>
+            {name}
+            {readOnly && <Chip size="small" label="Read only" sx={{ ml: 1 }} />}
+            <InsecureServerChip serverId={serverId} />
+          </>
+        }
+        secondary={serverId}
+      />
 
       <Stack direction="row" alignItems="center">
+        <FormControlLabel
+          label="Read only"
+          control={
+            <Switch
+              checked={readOnly}
+              disabled={isSettingReadOnly}
+              onChange={(_, checked) => setReadOnly(checked)}
+            />
+          }
+        />
+
         <Button
           onClick={() => {
             onLoad();
@@ -0,0 +1,38 @@
+import { useEffect } from "react";
+import { reportActivity } from "@/api/app/app.requests";
+
+/**
+ * Minimum time between activity reports
+ */
+const REPORT_INTERVAL_MS = 30_000;
+
+const ACTIVITY_EVENTS = ["pointerdown", "keydown", "wheel", "touchstart"];
+
+/**
+ * Report user input to the idle lock so the app does not lock while in use
+ */
+export function useActivityReporter() {
+  useEffect(() => {
+    let lastReport = 0;
+
+    const onActivity = () => {
+      const now = Date.now();
+      if (now - lastReport < REPORT_INTERVAL_MS) return;
+      lastReport = now;
+
+      reportActivity().catch((error) => {
+        console.error("failed to report activity", error);
+      });
+    };
+
+    for (const event of ACTIVITY_EVENTS) {
+      window.addEventListener(event, onActivity, { passive: true });
+    }
+
+    return () => {
+      for (const event of ACTIVITY_EVENTS) {
+        window.removeEventListener(event, onActivity);
+      }
+    };
+  }, []);
+}
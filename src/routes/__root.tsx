@@ -2,6 +2,9 @@ import { Outlet, createRootRouteWithContext } from "@tanstack/react-router";
 import { TanStackRouterDevtools } from "@tanstack/react-router-devtools";
 
 import Header from "../components/Header";
+import DatabaseMigrationAlert from "../components/DatabaseMigrationAlert";
+import { useActivityReporter } from "../hooks/use-activity-reporter";
+import { useMigrationCheck } from "@/api/app/app.queries";
 
 import TanStackQueryLayout from "../integrations/tanstack-query/layout.tsx";
 
@@ -12,14 +15,24 @@ interface MyRouterContext {
 }
 
 export const Route = createRootRouteWithContext<MyRouterContext>()({
-  component: () => (
+  component: RootComponent,
+});
+
+function RootComponent() {
+  useActivityReporter();
+
+  // Stay on the recovery screen while the app database is not migrated
+  const { isSuccess: isMigrated } = useMigrationCheck();
+
+  return (
     <>
       <Header />
+      <DatabaseMigrationAlert />
 
-      <Outlet />
+      {isMigrated && <Outlet />}
 
       <TanStackRouterDevtools />
       <TanStackQueryLayout />
     </>
-  ),
-});
+  );
+}
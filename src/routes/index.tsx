@@ -121,6 +121,7 @@ function App() {
                     key={server.id}
                     serverId={server.id}
                     name={server.name}
+                    readOnly={server.read_only}
                     onLoad={() => {
                       loadServerMutation.mutate(
                         {
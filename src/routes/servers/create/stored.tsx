@@ -110,11 +110,11 @@ function RouteComponent() {
           JSON.stringify(config)
         );
 
+        // The full envelope is kept, it records the key derivation
+        // parameters and whether a key file is required
         serverConfig = {
           type: ServerConfigType.Encrypted,
-          salt: encryptedConfig.salt,
-          nonce: encryptedConfig.nonce,
-          data: encryptedConfig.data,
+          ...encryptedConfig,
         };
       } else {
         serverConfig = {